@@ -0,0 +1,102 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use huffman_decoder::data::cache::{CacheManager, Cached, One};
+use huffman_decoder::data::catalog::Catalog;
+use huffman_decoder::data::format::ItemList;
+use huffman_decoder::data::mapped::{write_mapped, MappedCatalog};
+use std::collections::HashMap;
+
+// Catalog-sized lists with ids in the range the parser produces
+fn list(size: u32, name: &str) -> One {
+    (0..size)
+        .map(|i| ((20000 + i * 3).to_string(), format!("{} {}", name, i)))
+        .collect::<HashMap<_, _>>()
+        .into()
+}
+
+fn items(list: &One) -> ItemList {
+    ItemList::from_legacy(list.clone()).0
+}
+
+fn benchmark_lookups(c: &mut Criterion) {
+    let all = list(10_000, "Item");
+    let bloody = list(500, "Bloody");
+    let mut catalog = Catalog::new();
+    catalog.insert("all", items(&all));
+    catalog.insert("bloody", items(&bloody));
+
+    // A frame's worth of ids, about a third of them unknown
+    let ids: Vec<u32> = (0..1000).map(|i| 20000 + i * 2).collect();
+
+    let mut group = c.benchmark_group("catalog_lookup");
+
+    // What the server did before the index: format the id, hash the string, clone the value
+    group.bench_function("string_keys", |b| {
+        b.iter(|| {
+            for id in &ids {
+                let key = id.to_string();
+                let name = all.get(&key).map(str::to_string);
+                let in_bloody = bloody.get(&key).is_some();
+                black_box((name, in_bloody));
+            }
+        })
+    });
+
+    group.bench_function("u32_index", |b| {
+        b.iter(|| {
+            for &id in &ids {
+                let entry = catalog.lookup(id);
+                black_box((entry.name(), entry.in_list("bloody")));
+            }
+        })
+    });
+
+    let mut mapped = Catalog::new();
+    let (dir, _, map) = catalog_files(&all);
+    mapped.insert_index("all", Cached { data: MappedCatalog::open(&map).unwrap().into(), saved_at: 0 });
+    mapped.insert("bloody", items(&bloody));
+    group.bench_function("u32_mapped", |b| {
+        b.iter(|| {
+            for &id in &ids {
+                let entry = mapped.lookup(id);
+                black_box((entry.name(), entry.in_list("bloody")));
+            }
+        })
+    });
+
+    group.finish();
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+// The same list as a bincode catalog and a mapped one
+fn catalog_files(list: &One) -> (std::path::PathBuf, String, String) {
+    let dir = std::env::temp_dir().join(format!("h278_catalog_bench_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let bin = dir.join("all.bin").to_string_lossy().into_owned();
+    let map = dir.join("all.map").to_string_lossy().into_owned();
+    CacheManager::new(u64::MAX).save_cache(&items(list), &bin).unwrap();
+    write_mapped(&items(list), &map, 0).unwrap();
+    (dir, bin, map)
+}
+
+fn benchmark_open(c: &mut Criterion) {
+    let (dir, bin, map) = catalog_files(&list(100_000, "Item"));
+    let manager = CacheManager::new(u64::MAX);
+
+    let mut group = c.benchmark_group("catalog_open");
+    group.bench_function("bincode_100k", |b| {
+        b.iter(|| black_box(manager.load_cache(&bin).unwrap().data.len()))
+    });
+    group.bench_function("mapped_100k", |b| {
+        b.iter(|| black_box(MappedCatalog::open(&map).unwrap().len()))
+    });
+    // What the server does: open and check the whole file's checksum
+    group.bench_function("mapped_verified_100k", |b| {
+        b.iter(|| black_box(manager.load_mapped(&map).unwrap().len()))
+    });
+    group.finish();
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+criterion_group!(benches, benchmark_lookups, benchmark_open);
+criterion_main!(benches);
@@ -1,7 +1,5 @@
 // Add this to your stock_parser.rs or create a new file
 
-use std::time::Instant;
-
 pub fn parse_stock_simple(input: &str) -> Vec<(u32, u32)> {
     input
         .split('|')
@@ -26,15 +24,12 @@ pub fn parse_stock_iterator(input: &str) -> Vec<(u32, u32)> {
         .take_while(|s| !s.is_empty())
         .filter_map(|item| {
             let mut iter = item.split('-');
-            match (iter.next(), iter.next()) {
-                (Some(id), Some(stock)) => {
-                    if let (Ok(id_num), Ok(stock_num)) = (id.parse::<u32>(), stock.parse::<u32>()) {
-                        if stock_num > 0 {
-                            return Some((id_num, stock_num));
-                        }
+            if let (Some(id), Some(stock)) = (iter.next(), iter.next()) {
+                if let (Ok(id_num), Ok(stock_num)) = (id.parse::<u32>(), stock.parse::<u32>()) {
+                    if stock_num > 0 {
+                        return Some((id_num, stock_num));
                     }
                 }
-                _ => (),
             }
             None
         })
@@ -82,6 +77,7 @@ pub fn parse_stock_bytes(input: &[u8]) -> Vec<(u32, u32)> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::time::Instant;
 
     fn generate_test_data(size: usize) -> String {
         let mut result = String::with_capacity(size * 30);
@@ -103,11 +99,13 @@ mod tests {
         for _ in 0..5 {
             let start = Instant::now();
             let result = parse_stock_simple(small_data);
-            let duration = start.elapsed();
+            let _ = start.elapsed();
+            assert_eq!(result, vec![(20069, 1)]);
 
             let start = Instant::now();
             let result = parse_stock_iterator(small_data);
-            let duration = start.elapsed();
+            let _ = start.elapsed();
+            assert_eq!(result, vec![(20069, 1)]);
 
             let start = Instant::now();
             let result = parse_stock_bytes(small_data.as_ref());
@@ -135,7 +133,7 @@ mod tests {
             println!("Iterator implementation: {:?} - Found {} items", duration, result.len());
 
             let start = Instant::now();
-            let result = parse_stock_bytes((&medium_data).as_ref());
+            let result = parse_stock_bytes(medium_data.as_ref());
             let duration = start.elapsed();
             println!("Bytes implementation: {:?} - Found {} items", duration, result.len());
 
@@ -159,7 +157,7 @@ mod tests {
             println!("Iterator implementation: {:?} - Found {} items", duration, result.len());
 
             let start = Instant::now();
-            let result = parse_stock_bytes((&large_data).as_ref());
+            let result = parse_stock_bytes(large_data.as_ref());
             let duration = start.elapsed();
             println!("Bytes implementation: {:?} - Found {} items", duration, result.len());
 
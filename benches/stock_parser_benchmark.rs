@@ -3,6 +3,8 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 use crate::stock_parser::{parse_stock_bytes, parse_stock_iterator, parse_stock_simple};
 
+// The parser's own timing tests only run through the `stock_parser` target
+#[allow(dead_code, unused_imports)]
 pub mod stock_parser;
 // Replace with your actual crate name
 
@@ -40,7 +42,7 @@ fn benchmark_parsers(c: &mut Criterion) {
         b.iter(|| parse_stock_iterator(black_box(&large_data)))
     });
     group.bench_function("bytes_large", |b| {
-        b.iter(|| parse_stock_bytes(black_box((&large_data).as_ref())))
+        b.iter(|| parse_stock_bytes(black_box(large_data.as_ref())))
     });
 
     group.finish();
@@ -0,0 +1,260 @@
+// Round-trip latency and one-way throughput of the transports a producer can use to reach the
+// server. Every measurement runs against a real second process: this binary re-executes itself
+// with H278_BENCH_PEER set, and the copy plays the producer's end.
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use criterion::{BenchmarkId, Criterion, Throughput};
+    use huffman_decoder::memory::client::SharedMemoryClient;
+    use huffman_decoder::memory::server::SharedMemoryServer;
+    use huffman_decoder::memory::transport::{encode_frame, STREAM_HEADER_SIZE};
+    use std::io::{self, BufReader, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::process::{Child, Command, Stdio};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    // What the peer does with a frame, carried in the client id field
+    const ECHO: u32 = 0;
+    const SINK: u32 = 1;
+    const ACK: u32 = 2;
+    const SHUTDOWN: u32 = 3;
+
+    // A small alert-sized frame and the 26 KiB dump the old scratch programs moved
+    const PAYLOAD_SIZES: [usize; 2] = [64, 26 * 1024];
+    const THROUGHPUT_BATCH: usize = 64;
+    const SEGMENT_SIZE: usize = 4 * 1024 * 1024;
+
+    // The bench process's end of a connection to the peer
+    trait Link {
+        fn send(&mut self, op: u32, payload: &[u8]);
+        // Blocks for the next reply and returns its length
+        fn recv(&mut self) -> usize;
+    }
+
+    struct StreamLink<R, W> {
+        reader: R,
+        writer: W,
+        buffer: Vec<u8>,
+    }
+
+    impl<R: Read, W: Write> Link for StreamLink<R, W> {
+        fn send(&mut self, op: u32, payload: &[u8]) {
+            self.writer.write_all(&encode_frame(op, payload)).unwrap();
+            self.writer.flush().unwrap();
+        }
+
+        fn recv(&mut self) -> usize {
+            read_frame(&mut self.reader, &mut self.buffer).unwrap().unwrap();
+            self.buffer.len()
+        }
+    }
+
+    // Requests go into the peer's segment, replies come back through ours
+    struct ShmLink {
+        replies: SharedMemoryServer,
+        requests: SharedMemoryClient,
+    }
+
+    impl Link for ShmLink {
+        fn send(&mut self, op: u32, payload: &[u8]) {
+            self.requests.send(op, payload).unwrap();
+        }
+
+        fn recv(&mut self) -> usize {
+            loop {
+                if let Some(frame) = self.replies.next_frame().unwrap() {
+                    return frame.len();
+                }
+                self.replies.wait_for_data();
+            }
+        }
+    }
+
+    // Same framing as the stream transports in `memory::transport`, read synchronously into a
+    // reused buffer. `Ok(None)` on a clean end of stream.
+    fn read_frame(reader: &mut impl Read, buffer: &mut Vec<u8>) -> io::Result<Option<u32>> {
+        let mut header = [0u8; STREAM_HEADER_SIZE];
+        match reader.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let total_size = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+        let op = u32::from_le_bytes(header[4..].try_into().unwrap());
+
+        buffer.resize(total_size - 4, 0);
+        reader.read_exact(buffer)?;
+        Ok(Some(op))
+    }
+
+    fn spawn_peer(kind: &str, target: &str, stdio: bool) -> Child {
+        let piped = || if stdio { Stdio::piped() } else { Stdio::null() };
+        Command::new(std::env::current_exe().unwrap())
+            .env("H278_BENCH_PEER", kind)
+            .env("H278_BENCH_TARGET", target)
+            .stdin(piped())
+            .stdout(piped())
+            .spawn()
+            .unwrap()
+    }
+
+    // Named after the bench process, so the peer derives them from its parent's pid
+    fn segment_names(bench_pid: u32) -> (String, String) {
+        (format!("h278_bench_req_{}", bench_pid), format!("h278_bench_rep_{}", bench_pid))
+    }
+
+    // The peer creates its segment after we spawn it, so keep trying until it is ready
+    fn open_client(name: &str) -> SharedMemoryClient {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            match SharedMemoryClient::open(name) {
+                Ok(client) => return client,
+                Err(e) if Instant::now() > deadline => panic!("peer segment {} never appeared: {}", name, e),
+                Err(_) => thread::sleep(Duration::from_millis(1)),
+            }
+        }
+    }
+
+    fn connect(kind: &str) -> (Box<dyn Link>, Child) {
+        match kind {
+            "shm_notify" => {
+                let (requests, replies) = segment_names(std::process::id());
+                let replies = SharedMemoryServer::new(&replies, SEGMENT_SIZE).unwrap();
+                let child = spawn_peer(kind, "", false);
+                let requests = open_client(&requests);
+                (Box::new(ShmLink { replies, requests }), child)
+            }
+            "unix_socket" => {
+                let path = format!("/tmp/h278_bench_{}.sock", std::process::id());
+                let _ = std::fs::remove_file(&path);
+                let listener = UnixListener::bind(&path).unwrap();
+                let child = spawn_peer(kind, &path, false);
+                let (stream, _) = listener.accept().unwrap();
+                let _ = std::fs::remove_file(&path);
+                let reader = BufReader::new(stream.try_clone().unwrap());
+                (Box::new(StreamLink { reader, writer: stream, buffer: Vec::new() }), child)
+            }
+            "tcp_loopback" => {
+                let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+                let child = spawn_peer(kind, &listener.local_addr().unwrap().to_string(), false);
+                let (stream, _) = listener.accept().unwrap();
+                stream.set_nodelay(true).unwrap();
+                let reader = BufReader::new(stream.try_clone().unwrap());
+                (Box::new(StreamLink { reader, writer: stream, buffer: Vec::new() }), child)
+            }
+            "pipe" => {
+                let mut child = spawn_peer(kind, "", true);
+                let writer = child.stdin.take().unwrap();
+                let reader = BufReader::new(child.stdout.take().unwrap());
+                (Box::new(StreamLink { reader, writer, buffer: Vec::new() }), child)
+            }
+            _ => unreachable!("unknown transport {}", kind),
+        }
+    }
+
+    pub fn transports(c: &mut Criterion) {
+        for kind in ["shm_notify", "unix_socket", "tcp_loopback", "pipe"] {
+            let (mut link, mut child) = connect(kind);
+
+            let mut group = c.benchmark_group("transport_round_trip");
+            for size in PAYLOAD_SIZES {
+                let payload = vec![0xA5u8; size];
+                group.throughput(Throughput::Bytes(size as u64));
+                group.bench_with_input(BenchmarkId::new(kind, size), &payload, |b, payload| {
+                    b.iter(|| {
+                        link.send(ECHO, payload);
+                        assert_eq!(link.recv(), payload.len());
+                    })
+                });
+            }
+            group.finish();
+
+            let mut group = c.benchmark_group("transport_throughput");
+            for size in PAYLOAD_SIZES {
+                let payload = vec![0xA5u8; size];
+                group.throughput(Throughput::Bytes((size * THROUGHPUT_BATCH) as u64));
+                group.bench_with_input(BenchmarkId::new(kind, size), &payload, |b, payload| {
+                    b.iter(|| {
+                        for _ in 0..THROUGHPUT_BATCH {
+                            link.send(SINK, payload);
+                        }
+                        // Only counts once the peer has taken every frame
+                        link.send(ACK, &[]);
+                        link.recv();
+                    })
+                });
+            }
+            group.finish();
+
+            link.send(SHUTDOWN, &[]);
+            drop(link);
+            child.wait().unwrap();
+        }
+    }
+
+    // The peer process: answers the bench process until told to shut down
+    pub fn run_peer(kind: &str) {
+        let target = std::env::var("H278_BENCH_TARGET").unwrap();
+        match kind {
+            "shm_notify" => {
+                let (requests, replies) = segment_names(std::os::unix::process::parent_id());
+                let mut requests = SharedMemoryServer::new(&requests, SEGMENT_SIZE).unwrap();
+                let replies = SharedMemoryClient::open(&replies).unwrap();
+                loop {
+                    while let Some(frame) = requests.next_frame().unwrap() {
+                        match frame.client_id() {
+                            ECHO => replies.send(ECHO, &frame).unwrap(),
+                            ACK => replies.send(ACK, &[]).unwrap(),
+                            SHUTDOWN => return,
+                            _ => {}
+                        }
+                    }
+                    requests.wait_for_data();
+                }
+            }
+            "unix_socket" => {
+                let stream = UnixStream::connect(&target).unwrap();
+                serve_stream(BufReader::new(stream.try_clone().unwrap()), stream);
+            }
+            "tcp_loopback" => {
+                let stream = TcpStream::connect(&target).unwrap();
+                stream.set_nodelay(true).unwrap();
+                serve_stream(BufReader::new(stream.try_clone().unwrap()), stream);
+            }
+            "pipe" => serve_stream(io::stdin().lock(), io::stdout().lock()),
+            _ => unreachable!("unknown transport {}", kind),
+        }
+    }
+
+    fn serve_stream(mut reader: impl Read, mut writer: impl Write) {
+        let mut buffer = Vec::new();
+        while let Some(op) = read_frame(&mut reader, &mut buffer).unwrap() {
+            match op {
+                ECHO => writer.write_all(&encode_frame(ECHO, &buffer)).unwrap(),
+                ACK => writer.write_all(&encode_frame(ACK, &[])).unwrap(),
+                SHUTDOWN => return,
+                _ => continue,
+            }
+            writer.flush().unwrap();
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn main() {
+    if let Ok(kind) = std::env::var("H278_BENCH_PEER") {
+        linux::run_peer(&kind);
+        return;
+    }
+
+    let mut criterion = criterion::Criterion::default().configure_from_args();
+    linux::transports(&mut criterion);
+    criterion.final_summary();
+}
+
+#[cfg(not(target_os = "linux"))]
+fn main() {
+    eprintln!("transport benchmarks spawn Linux peer processes and only run on Linux");
+}
@@ -0,0 +1,105 @@
+use huffman_decoder::data::cache::{write_cache, CacheManager, Cached};
+use huffman_decoder::data::format::{self, LegacyMigration, CATALOG_VERSION};
+use huffman_decoder::data::mapped::{self, MappedCatalog};
+use huffman_decoder::data::import::{parse_list, to_json, ListFormat};
+use std::error::Error as StdError;
+use std::path::Path;
+use std::{env, fs, process};
+
+const USAGE: &str = "\
+Usage:
+  catalog build <input.csv|input.tsv|input.json> <output.bin> [--format csv|tsv|json] [--mapped]
+  catalog dump <input.bin> [output.json]
+  catalog migrate <input.bin> [output.bin]
+
+build    converts an id,name list (or JSON item records) into the catalog file the server loads,
+         or with --mapped into the memory-mapped format
+dump     writes a catalog of either format back out as JSON records (to stdout without an output path)
+migrate  rewrites a catalog in the current format version (in place without an output path)";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Err(err) = run(&args) {
+        eprintln!("Error: {}", err);
+        process::exit(1);
+    }
+}
+
+fn run(args: &[String]) -> Result<(), Box<dyn StdError>> {
+    let mut args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let as_mapped = match args.iter().position(|&arg| arg == "--mapped") {
+        Some(index) => {
+            args.remove(index);
+            true
+        }
+        None => false,
+    };
+
+    match args.as_slice() {
+        ["build", input, output] => build(input, output, None, as_mapped),
+        ["build", input, output, "--format", format] => build(input, output, Some(format), as_mapped),
+        ["dump", input] => dump(input, None),
+        ["dump", input, output] => dump(input, Some(output)),
+        ["migrate", input] => migrate(input, input),
+        ["migrate", input, output] => migrate(input, output),
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    }
+}
+
+fn build(input: &str, output: &str, format: Option<&str>, as_mapped: bool) -> Result<(), Box<dyn StdError>> {
+    let format = match format {
+        Some(name) => ListFormat::from_name(name).ok_or_else(|| format!("unknown format '{}'", name))?,
+        None => ListFormat::from_path(Path::new(input))
+            .ok_or_else(|| format!("can't tell the format of {}; pass --format", input))?,
+    };
+
+    let list = parse_list(&fs::read_to_string(input)?, format)
+        .map_err(|err| format!("{}: {}", input, err))?;
+    if as_mapped {
+        let saved_at = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?.as_secs();
+        mapped::write_mapped(&list, output, saved_at)?;
+    } else {
+        CacheManager::new(u64::MAX).save_cache(&list, output)?;
+    }
+    eprintln!("Wrote {} items to {}", list.len(), output);
+    Ok(())
+}
+
+fn dump(input: &str, output: Option<&str>) -> Result<(), Box<dyn StdError>> {
+    // Dumping is for review, so an expired file is still worth reading
+    let cached = if mapped::is_mapped(input)? {
+        let catalog = MappedCatalog::open(input)?;
+        catalog.verify()?;
+        Cached { data: catalog.to_list(), saved_at: catalog.saved_at() }
+    } else {
+        CacheManager::new(u64::MAX).load_cache(input)?
+    };
+    let json = to_json(&cached.data);
+    match output {
+        Some(output) => fs::write(output, json + "\n")?,
+        None => println!("{}", json),
+    }
+    eprintln!("{} items, saved {}s ago", cached.data.len(), cached.age().as_secs());
+    Ok(())
+}
+
+fn migrate(input: &str, output: &str) -> Result<(), Box<dyn StdError>> {
+    let (cached, version, migration) = format::decode(&fs::read(input)?)?;
+    if migration != LegacyMigration::default() {
+        eprintln!(
+            "Dropped {} entries without a numeric id and {} repeating another entry's id",
+            migration.non_numeric, migration.duplicate_ids,
+        );
+    }
+    if version == CATALOG_VERSION && input == output {
+        eprintln!("{} is already version {}", input, version);
+        return Ok(());
+    }
+    // Keep the original timestamp so expiry still reflects when the data was built
+    write_cache(&cached.data, output, cached.saved_at)?;
+    eprintln!("Migrated {} items from version {} to {} in {}", cached.data.len(), version, CATALOG_VERSION, output);
+    Ok(())
+}
@@ -0,0 +1,69 @@
+use huffman_decoder::data::history::HistoryReader;
+use std::error::Error as StdError;
+use std::{env, process};
+
+const USAGE: &str = "\
+Usage:
+  history item <dir> <id> [since_ms [until_ms]]
+  history last-seen <dir> <id>
+  history restocks <dir> <id> [since_ms [until_ms]]
+
+item       prints every record of the item as received_at, client, stock, price, timestamp
+last-seen  prints the last time the item was seen with stock
+restocks   counts how often clients reported more stock than before, and how far apart
+
+Times are Unix milliseconds of when the server received the frame.";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Err(err) = run(&args) {
+        eprintln!("Error: {}", err);
+        process::exit(1);
+    }
+}
+
+fn run(args: &[String]) -> Result<(), Box<dyn StdError>> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let (command, dir, id, range) = match args.as_slice() {
+        [command, dir, id, range @ ..] if range.len() <= 2 => (*command, *dir, id.parse::<u32>()?, range),
+        _ => usage(),
+    };
+    let since = range.first().map(|since| since.parse()).transpose()?.unwrap_or(0);
+    let until = range.get(1).map(|until| until.parse()).transpose()?.unwrap_or(u64::MAX);
+    let reader = HistoryReader::new(dir);
+
+    match command {
+        "item" => {
+            for observation in reader.item_history(id, since, until)? {
+                println!(
+                    "{}\t{}\t{}\t{}\t{}",
+                    observation.received_at, observation.client_id, observation.stock, observation.price, observation.timestamp,
+                );
+            }
+        }
+        "last-seen" if range.is_empty() => match reader.last_seen(id)? {
+            Some(observation) => println!(
+                "{} (client {}, stock {})",
+                observation.received_at, observation.client_id, observation.stock,
+            ),
+            None => println!("never"),
+        },
+        "restocks" => {
+            let stats = reader.restocks(id, since, until)?;
+            println!("{} observations, {} restocks", stats.observations, stats.restocks);
+            if let (Some(first), Some(last)) = (stats.first_restock, stats.last_restock) {
+                println!("first {}, last {}", first, last);
+            }
+            if let Some(interval) = stats.mean_interval() {
+                println!("every {}s on average", interval.as_secs());
+            }
+        }
+        _ => usage(),
+    }
+    Ok(())
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
@@ -0,0 +1,160 @@
+use huffman_decoder::huffman::encoder::encode;
+use huffman_decoder::memory::client::SharedMemoryClient;
+use huffman_decoder::memory::transport::encode_frame;
+use huffman_decoder::service::simulator::{SimulatorConfig, StockSimulator};
+use std::error::Error as StdError;
+use std::io::Write;
+use std::net::TcpStream;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::{env, process, thread};
+
+const USAGE: &str = "\
+Usage: producer-sim [options]
+
+Where frames go (default: --segment h278):
+  --segment <name>       shared memory segment of a running server
+  --tcp <address>        stream transport over TCP
+  --unix <path>          stream transport over a Unix socket
+
+What is sent:
+  --items <n>            items per frame (1000), ids from --first-id (20000)
+  --clients <n>          client ids to take turns as (4)
+  --restock <p>          chance per item and frame of a restock (0.001)
+  --sell <p>             chance per item and frame of selling one unit (0.2)
+  --seed <n>             generator seed; the same seed sends the same frames
+
+How fast:
+  --rate <fps>           frames per second, 0 for as fast as the server takes them (10)
+  --burst-every <secs>   also send a burst of frames back to back this often
+  --burst-frames <n>     frames per burst (100)
+  --frames <n>           stop after this many frames";
+
+enum Sink {
+    SharedMemory(SharedMemoryClient),
+    Stream(Box<dyn Write>),
+}
+
+impl Sink {
+    fn send(&mut self, client_id: u32, payload: &[u8]) -> std::io::Result<()> {
+        match self {
+            Sink::SharedMemory(client) => client.send(client_id, payload),
+            Sink::Stream(stream) => stream.write_all(&encode_frame(client_id, payload)),
+        }
+    }
+}
+
+struct Options {
+    sink: Sink,
+    simulator: SimulatorConfig,
+    rate: f64,
+    burst_every: Option<Duration>,
+    burst_frames: u64,
+    frames: Option<u64>,
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = match parse(&args) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(err) = run(options) {
+        eprintln!("Error: {}", err);
+        process::exit(1);
+    }
+}
+
+fn parse(args: &[String]) -> Result<Options, Box<dyn StdError>> {
+    let mut sink = None;
+    let mut simulator = SimulatorConfig::default();
+    let (mut rate, mut burst_every, mut burst_frames, mut frames) = (10.0, None, 100, None);
+
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        let value = args.next().ok_or_else(|| format!("{} needs a value", flag))?;
+        match flag.as_str() {
+            "--segment" => sink = Some(Sink::SharedMemory(SharedMemoryClient::open(value)?)),
+            "--tcp" => {
+                let stream = TcpStream::connect(value)?;
+                stream.set_nodelay(true)?;
+                sink = Some(Sink::Stream(Box::new(stream)));
+            }
+            #[cfg(unix)]
+            "--unix" => sink = Some(Sink::Stream(Box::new(std::os::unix::net::UnixStream::connect(value)?))),
+            "--items" => simulator.items = value.parse()?,
+            "--first-id" => simulator.first_id = value.parse()?,
+            "--clients" => simulator.clients = value.parse()?,
+            "--restock" => simulator.restock_probability = value.parse()?,
+            "--sell" => simulator.sell_probability = value.parse()?,
+            "--seed" => simulator.seed = value.parse()?,
+            "--rate" => rate = value.parse()?,
+            "--burst-every" => burst_every = Some(Duration::from_secs_f64(value.parse()?)),
+            "--burst-frames" => burst_frames = value.parse()?,
+            "--frames" => frames = Some(value.parse()?),
+            other => return Err(format!("unknown option '{}'", other).into()),
+        }
+    }
+
+    let sink = match sink {
+        Some(sink) => sink,
+        None => Sink::SharedMemory(SharedMemoryClient::open("h278")?),
+    };
+    Ok(Options { sink, simulator, rate, burst_every, burst_frames, frames })
+}
+
+fn run(mut options: Options) -> Result<(), Box<dyn StdError>> {
+    let mut simulator = StockSimulator::new(options.simulator.clone());
+    let interval = (options.rate > 0.0).then(|| Duration::from_secs_f64(1.0 / options.rate));
+    let started = Instant::now();
+    let mut next_frame = started;
+    let mut next_burst = options.burst_every.map(|every| started + every);
+    let mut last_report = started;
+    let (mut sent, mut raw_bytes, mut encoded_bytes) = (0u64, 0u64, 0u64);
+
+    while options.frames.is_none_or(|frames| sent < frames) {
+        // A burst goes out on top of the steady rate
+        let mut due = 1;
+        if next_burst.is_some_and(|burst| Instant::now() >= burst) {
+            due += options.burst_frames;
+            next_burst = next_burst.zip(options.burst_every).map(|(burst, every)| burst + every);
+        }
+
+        for _ in 0..due {
+            if options.frames.is_some_and(|frames| sent >= frames) {
+                break;
+            }
+            let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+            let (client_id, payload) = simulator.next_frame(timestamp);
+            let frame = encode(payload.as_bytes())?;
+            // A snapshot too large for a ring slot is refused here with the slot size
+            options.sink.send(client_id, &frame)?;
+            sent += 1;
+            raw_bytes += payload.len() as u64;
+            encoded_bytes += frame.len() as u64;
+        }
+
+        if last_report.elapsed() >= Duration::from_secs(1) {
+            last_report = Instant::now();
+            let secs = started.elapsed().as_secs_f64();
+            println!("{} frames ({:.0}/s), {} items in stock", sent, sent as f64 / secs, simulator.in_stock());
+        }
+
+        if let Some(interval) = interval {
+            next_frame += interval;
+            if let Some(wait) = next_frame.checked_duration_since(Instant::now()) {
+                thread::sleep(wait);
+            }
+        }
+    }
+
+    let secs = started.elapsed().as_secs_f64();
+    println!(
+        "Sent {} frames in {:.2}s ({:.0}/s), {} bytes encoded from {} ({:.0}%)",
+        sent, secs, sent as f64 / secs, encoded_bytes, raw_bytes,
+        100.0 * encoded_bytes as f64 / raw_bytes.max(1) as f64,
+    );
+    Ok(())
+}
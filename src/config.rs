@@ -0,0 +1,91 @@
+use serde::Deserialize;
+use std::{fmt, fs};
+use std::error::Error as StdError;
+use crate::data::catalog::{default_sources, CacheConfig, CatalogSource};
+use crate::data::history::HistoryConfig;
+use crate::data::reload::ReloadConfig;
+use crate::memory::capture::CaptureConfig;
+use crate::memory::transport::TransportConfig;
+use crate::service::alert_queue::AlertQueueConfig;
+use crate::service::dedup::DedupConfig;
+use crate::service::rules::{default_rules, Rule};
+use crate::service::template::AlertTemplateConfig;
+
+#[derive(Debug)]
+pub enum ConfigError {
+    IoError(std::io::Error),
+    ParseError(serde_json::Error),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::IoError(e) => write!(f, "IO error: {}", e),
+            ConfigError::ParseError(e) => write!(f, "Parse error: {}", e),
+        }
+    }
+}
+
+impl StdError for ConfigError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            ConfigError::IoError(e) => Some(e),
+            ConfigError::ParseError(e) => Some(e),
+        }
+    }
+}
+
+impl From<std::io::Error> for ConfigError {
+    fn from(err: std::io::Error) -> Self {
+        ConfigError::IoError(err)
+    }
+}
+
+impl From<serde_json::Error> for ConfigError {
+    fn from(err: serde_json::Error) -> Self {
+        ConfigError::ParseError(err)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct AppConfig {
+    pub alerts: AlertTemplateConfig,
+    pub alert_queue: AlertQueueConfig,
+    pub dedup: DedupConfig,
+    pub rules: Vec<Rule>,
+    pub catalogs: Vec<CatalogSource>,
+    pub cache: CacheConfig,
+    pub catalog_reload: ReloadConfig,
+    pub transport: TransportConfig,
+    pub history: HistoryConfig,
+    pub capture: CaptureConfig,
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            alerts: AlertTemplateConfig::default(),
+            alert_queue: AlertQueueConfig::default(),
+            dedup: DedupConfig::default(),
+            rules: default_rules(),
+            catalogs: default_sources(),
+            cache: CacheConfig::default(),
+            catalog_reload: ReloadConfig::default(),
+            transport: TransportConfig::default(),
+            history: HistoryConfig::default(),
+            capture: CaptureConfig::default(),
+        }
+    }
+}
+
+impl AppConfig {
+    // A missing file means "use the defaults", anything else that goes wrong is an error
+    pub fn load(path: &str) -> Result<Self, ConfigError> {
+        match fs::read_to_string(path) {
+            Ok(contents) => Ok(serde_json::from_str(&contents)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
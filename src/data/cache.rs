@@ -1,17 +1,57 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::{fmt, fs};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use serde::de::StdError;
+use crate::data::format::{self, ItemList, LegacyMigration};
+use crate::data::mapped::{self, MappedCatalog};
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct One(HashMap<String, String>);
 
+impl From<HashMap<String, String>> for One {
+    fn from(items: HashMap<String, String>) -> Self {
+        Self(items)
+    }
+}
+
+impl IntoIterator for One {
+    type Item = (String, String);
+    type IntoIter = std::collections::hash_map::IntoIter<String, String>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl One {
+    pub fn get(&self, id: &str) -> Option<&str> {
+        self.0.get(id).map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(id, name)| (id.as_str(), name.as_str()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
 #[derive(Debug)]
 pub enum CacheError {
     IoError(std::io::Error),
     SerializationError(bincode::Error),
     CacheExpired,
+    // A catalog header with a schema version this build can't read
+    UnsupportedVersion(u32),
+    // A truncated file or a checksum mismatch
+    Corrupt(String),
 }
 
 impl fmt::Display for CacheError {
@@ -20,6 +60,12 @@ impl fmt::Display for CacheError {
             CacheError::IoError(e) => write!(f, "IO error: {}", e),
             CacheError::SerializationError(e) => write!(f, "Serialization error: {}", e),
             CacheError::CacheExpired => write!(f, "Cache has expired"),
+            CacheError::UnsupportedVersion(version) => write!(
+                f,
+                "Unsupported catalog format version {} (this build reads up to {})",
+                version, format::CATALOG_VERSION,
+            ),
+            CacheError::Corrupt(reason) => write!(f, "Corrupt cache: {}", reason),
         }
     }
 }
@@ -28,7 +74,7 @@ impl StdError for CacheError {
         match self {
             CacheError::IoError(e) => Some(e),
             CacheError::SerializationError(e) => Some(e),
-            CacheError::CacheExpired => None,
+            _ => None,
         }
     }
 }
@@ -45,64 +91,204 @@ impl From<bincode::Error> for CacheError {
     }
 }
 
+// A cache file's contents along with when it was written
+#[derive(Debug, Clone)]
+pub struct Cached<T> {
+    pub data: T,
+    // Seconds since the Unix epoch
+    pub saved_at: u64,
+}
+
+impl<T> Cached<T> {
+    pub fn age(&self) -> Duration {
+        self.age_at(unix_now())
+    }
+
+    // Timestamps from the future (clock changes) count as fresh
+    pub fn age_at(&self, now: u64) -> Duration {
+        Duration::from_secs(now.saturating_sub(self.saved_at))
+    }
+}
+
+pub type CacheLoader = Arc<dyn Fn(&str) -> Result<ItemList, CacheError> + Send + Sync>;
+
+// What `load_cache` does with a file older than the cache duration
+#[derive(Deserialize, Clone, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ExpiryPolicy {
+    #[default]
+    Reject,
+    WarnAndUse,
+    // Rebuilds the data with the loader (given the cache path) and rewrites the file
+    #[serde(skip)]
+    Refresh(CacheLoader),
+}
+
+impl fmt::Debug for ExpiryPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExpiryPolicy::Reject => write!(f, "Reject"),
+            ExpiryPolicy::WarnAndUse => write!(f, "WarnAndUse"),
+            ExpiryPolicy::Refresh(_) => write!(f, "Refresh(..)"),
+        }
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
 pub struct CacheManager {
     cache_duration: u64,
+    policy: ExpiryPolicy,
 }
 
 impl CacheManager {
     pub fn new(cache_duration: u64) -> Self {
-        Self { cache_duration }
+        Self { cache_duration, policy: ExpiryPolicy::default() }
     }
 
-    pub(crate) fn load_cache(&self, filename: &str) -> Result<One, CacheError> {
-        let data = fs::read(filename)?;
-        let (_timestamp, cached_data): (u64, Vec<u8>) = bincode::deserialize(&data)?;
-
+    pub fn with_policy(mut self, policy: ExpiryPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
 
-        let deserialized: One = bincode::deserialize(&cached_data)?;
-        Ok(deserialized)
+    // Files in an older format are migrated in memory; `save_cache` writes the current one
+    pub fn load_cache(&self, filename: &str) -> Result<Cached<ItemList>, CacheError> {
+        let (cached, _, migration) = format::decode(&fs::read(filename)?)?;
+        if migration != LegacyMigration::default() {
+            eprintln!(
+                "Warning: migrating {} dropped {} entries without a numeric id and {} repeating another entry's id",
+                filename, migration.non_numeric, migration.duplicate_ids,
+            );
+        }
+        match self.check_expiry(filename, &cached)? {
+            None => Ok(cached),
+            Some(loader) => {
+                let data = loader(filename)?;
+                let saved_at = self.save_cache(&data, filename)?;
+                Ok(Cached { data, saved_at })
+            }
+        }
     }
 
+    // The memory-mapped alternative to `load_cache`, under the same expiry policy. A refreshed
+    // list is written back in the mapped format. The catalog is about to be served, so its
+    // checksum is checked too.
+    pub fn load_mapped(&self, filename: &str) -> Result<MappedCatalog, CacheError> {
+        let catalog = MappedCatalog::open(filename)?;
+        catalog.verify()?;
+        let age = Cached { data: (), saved_at: catalog.saved_at() };
+        match self.check_expiry(filename, &age)? {
+            None => Ok(catalog),
+            Some(loader) => {
+                let data = loader(filename)?;
+                mapped::write_mapped(&data, filename, unix_now())?;
+                let catalog = MappedCatalog::open(filename)?;
+                catalog.verify()?;
+                Ok(catalog)
+            }
+        }
+    }
 
-    fn save_cache(&self, data: &One, filename: &str) -> Result<(), CacheError> {
-        let serialized = bincode::serialize(&data)?;
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
+    // Whether a file can be used as is, or the loader to rebuild it with
+    fn check_expiry<T>(&self, filename: &str, cached: &Cached<T>) -> Result<Option<&CacheLoader>, CacheError> {
+        let age = cached.age();
+        if age.as_secs() <= self.cache_duration {
+            return Ok(None);
+        }
 
-        let cache_data = (timestamp, serialized);
-        let cache_serialized = bincode::serialize(&cache_data)?;
+        match &self.policy {
+            ExpiryPolicy::Reject => Err(CacheError::CacheExpired),
+            ExpiryPolicy::WarnAndUse => {
+                eprintln!(
+                    "Warning: cache {} is {}s old (limit {}s), using it anyway",
+                    filename, age.as_secs(), self.cache_duration,
+                );
+                Ok(None)
+            }
+            ExpiryPolicy::Refresh(loader) => Ok(Some(loader)),
+        }
+    }
 
-        fs::write(filename, cache_serialized)?;
-        Ok(())
+    // Returns the timestamp written with the data
+    pub fn save_cache(&self, data: &ItemList, filename: &str) -> Result<u64, CacheError> {
+        let timestamp = unix_now();
+        write_cache(data, filename, timestamp)?;
+        Ok(timestamp)
     }
 }
 
-pub struct H9123 {
-    pub first_list: One,
-    pub second_list: One,
+// Writes with a given timestamp, e.g. to migrate a file without making it look fresh
+pub fn write_cache(data: &ItemList, filename: &str, timestamp: u64) -> Result<(), CacheError> {
+    let bytes = format::encode(data, timestamp)?;
+
+    // Write then rename, so a reader or watcher never sees a half-written file
+    let tmp = format!("{}.tmp", filename);
+    fs::write(&tmp, bytes)?;
+    fs::rename(&tmp, filename)?;
+    Ok(())
 }
 
-impl H9123 {
-    pub fn new(first_list: One, second_list: One) -> Self {
-        Self {
-            first_list,
-            second_list,
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::format::ItemRecord;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const DAY: u64 = 24 * 60 * 60;
+
+    fn stale_cache(test: &str) -> String {
+        let path = std::env::temp_dir()
+            .join(format!("h278_cache_{}_{}.bin", test, std::process::id()))
+            .to_string_lossy()
+            .into_owned();
+        let data = ItemList::from_iter([(1, ItemRecord::named("Sword"))]);
+        write_cache(&data, &path, unix_now() - DAY).unwrap();
+        path
     }
 
-    pub fn search_standard(&self, ids: &[String]) -> Vec<(String, Option<String>, Option<String>)> {
-        ids.iter()
-            .map(|id| {
-                let first_value = self.first_list.0.get(id).cloned();
-                let second_value = self.second_list.0.get(id).cloned();
-                (id.clone(), first_value, second_value)
-            })
-            .collect()
+    #[test]
+    fn expired_cache_is_rejected_or_used_with_its_age() {
+        let path = stale_cache("policy");
+
+        let fresh_enough = CacheManager::new(2 * DAY).load_cache(&path).unwrap();
+        assert!(fresh_enough.age() >= Duration::from_secs(DAY));
+
+        let rejected = CacheManager::new(3600).load_cache(&path);
+        assert!(matches!(rejected, Err(CacheError::CacheExpired)));
+
+        let used = CacheManager::new(3600).with_policy(ExpiryPolicy::WarnAndUse).load_cache(&path).unwrap();
+        assert_eq!(used.data.get(1).map(|item| item.name.as_str()), Some("Sword"));
+        assert!(used.age() >= Duration::from_secs(DAY));
+
+        fs::remove_file(&path).unwrap();
     }
 
-}
+    #[test]
+    fn refresh_rebuilds_and_rewrites_an_expired_cache() {
+        let path = stale_cache("refresh");
+        let calls = Arc::new(AtomicUsize::new(0));
+        let loader: CacheLoader = Arc::new({
+            let calls = Arc::clone(&calls);
+            move |_| {
+                calls.fetch_add(1, Ordering::SeqCst);
+                Ok(ItemList::from_iter([(1, ItemRecord::named("Fresh Sword"))]))
+            }
+        });
+        let manager = CacheManager::new(3600).with_policy(ExpiryPolicy::Refresh(loader));
 
+        let refreshed = manager.load_cache(&path).unwrap();
+        assert_eq!(refreshed.data.get(1).unwrap().name, "Fresh Sword");
+        assert!(refreshed.age() < Duration::from_secs(60));
 
+        // The rewritten file is fresh, so the loader isn't needed again
+        assert_eq!(manager.load_cache(&path).unwrap().data.get(1).unwrap().name, "Fresh Sword");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
 
+        fs::remove_file(&path).unwrap();
+    }
+}
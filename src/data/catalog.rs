@@ -0,0 +1,348 @@
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::data::cache::{CacheError, CacheManager, Cached, ExpiryPolicy};
+use crate::data::format::{ItemList, ItemRecord};
+use crate::data::mapped::{self, MappedCatalog, MappedItem};
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct CatalogSource {
+    pub name: String,
+    pub path: String,
+}
+
+pub fn default_sources() -> Vec<CatalogSource> {
+    vec![
+        CatalogSource { name: "all".to_string(), path: "src/data/db/all.bin".to_string() },
+        CatalogSource { name: "bloody".to_string(), path: "src/data/db/bloody.bin".to_string() },
+    ]
+}
+
+// How old catalog files may be. Unlike `CacheManager`, expired catalogs are used with a warning
+// by default, so an old deployment keeps starting.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct CacheConfig {
+    pub max_age_secs: u64,
+    pub on_expired: ExpiryPolicy,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self { max_age_secs: 3600, on_expired: ExpiryPolicy::WarnAndUse }
+    }
+}
+
+impl CacheConfig {
+    pub fn manager(&self) -> CacheManager {
+        CacheManager::new(self.max_age_secs).with_policy(self.on_expired.clone())
+    }
+}
+
+// One list keyed by numeric id, sorted for binary search. Built once when a file is loaded so a
+// lookup neither formats the id nor allocates; a mapped file is searched where it lies.
+#[derive(Debug, Clone)]
+pub struct ItemIndex {
+    backend: Backend,
+}
+
+#[derive(Debug, Clone)]
+enum Backend {
+    // Sorted, and kept apart from the records so the search only touches a dense array of ids
+    Owned { ids: Vec<u32>, records: Vec<ItemRecord> },
+    Mapped(Arc<MappedCatalog>),
+}
+
+impl Default for ItemIndex {
+    fn default() -> Self {
+        ItemIndex { backend: Backend::Owned { ids: Vec::new(), records: Vec::new() } }
+    }
+}
+
+impl From<ItemList> for ItemIndex {
+    fn from(list: ItemList) -> Self {
+        // The list iterates in id order with no repeats
+        let (ids, records) = list.into_iter().unzip();
+        ItemIndex { backend: Backend::Owned { ids, records } }
+    }
+}
+
+impl From<MappedCatalog> for ItemIndex {
+    fn from(catalog: MappedCatalog) -> Self {
+        ItemIndex { backend: Backend::Mapped(Arc::new(catalog)) }
+    }
+}
+
+impl ItemIndex {
+    pub fn get(&self, id: u32) -> Option<ItemRef<'_>> {
+        match &self.backend {
+            Backend::Owned { ids, records } => ids.binary_search(&id).ok().map(|index| ItemRef::Owned(&records[index])),
+            Backend::Mapped(catalog) => catalog.get(id).map(ItemRef::Mapped),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.backend {
+            Backend::Owned { ids, .. } => ids.len(),
+            Backend::Mapped(catalog) => catalog.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_mapped(&self) -> bool {
+        matches!(self.backend, Backend::Mapped(_))
+    }
+
+    pub fn iter(&self) -> Box<dyn Iterator<Item = (u32, ItemRef<'_>)> + '_> {
+        match &self.backend {
+            Backend::Owned { ids, records } => Box::new(ids.iter().copied().zip(records.iter().map(ItemRef::Owned))),
+            Backend::Mapped(catalog) => Box::new(catalog.iter().map(|(id, item)| (id, ItemRef::Mapped(item)))),
+        }
+    }
+}
+
+// An item's record, wherever the list keeps it
+#[derive(Debug, Clone, Copy)]
+pub enum ItemRef<'a> {
+    Owned(&'a ItemRecord),
+    Mapped(MappedItem<'a>),
+}
+
+impl<'a> ItemRef<'a> {
+    pub fn name(&self) -> &'a str {
+        match self {
+            ItemRef::Owned(record) => &record.name,
+            ItemRef::Mapped(item) => item.name(),
+        }
+    }
+
+    pub fn category(&self) -> Option<&'a str> {
+        match self {
+            ItemRef::Owned(record) => record.category.as_deref(),
+            ItemRef::Mapped(item) => item.category(),
+        }
+    }
+
+    pub fn image_url(&self) -> Option<&'a str> {
+        match self {
+            ItemRef::Owned(record) => record.image_url.as_deref(),
+            ItemRef::Mapped(item) => item.image_url(),
+        }
+    }
+
+    pub fn tags(&self) -> Vec<&'a str> {
+        match self {
+            ItemRef::Owned(record) => record.tags.iter().map(String::as_str).collect(),
+            ItemRef::Mapped(item) => item.tags().collect(),
+        }
+    }
+
+    pub fn min_stock(&self) -> Option<u32> {
+        match self {
+            ItemRef::Owned(record) => record.min_stock,
+            ItemRef::Mapped(item) => item.min_stock(),
+        }
+    }
+
+    pub fn max_price(&self) -> Option<u64> {
+        match self {
+            ItemRef::Owned(record) => record.max_price,
+            ItemRef::Mapped(item) => item.max_price(),
+        }
+    }
+
+    pub fn to_record(&self) -> ItemRecord {
+        match self {
+            ItemRef::Owned(record) => ItemRecord::clone(record),
+            ItemRef::Mapped(item) => item.to_record(),
+        }
+    }
+}
+
+// Loads a catalog file in whichever format it is in: mapped files are mapped, bincode files are
+// read and indexed
+pub fn load_list(cache_manager: &CacheManager, path: &str) -> Result<Cached<ItemIndex>, CacheError> {
+    if mapped::is_mapped(path)? {
+        let catalog = cache_manager.load_mapped(path)?;
+        return Ok(Cached { saved_at: catalog.saved_at(), data: catalog.into() });
+    }
+    let list = cache_manager.load_cache(path)?;
+    Ok(Cached { data: list.data.into(), saved_at: list.saved_at })
+}
+
+// One id looked up across every list of a catalog. Memberships are answered on demand, so
+// building an entry costs nothing.
+#[derive(Debug, Clone, Copy)]
+pub struct CatalogEntry<'a> {
+    catalog: &'a Catalog,
+    id: u32,
+}
+
+impl<'a> CatalogEntry<'a> {
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    // Each list holding the id, in config order, with the record it stores for it
+    pub fn memberships(&self) -> impl Iterator<Item = (&'a str, ItemRef<'a>)> + 'a {
+        let id = self.id;
+        self.catalog.lists.iter()
+            .filter_map(move |(name, list)| list.data.get(id).map(|value| (name.as_str(), value)))
+    }
+
+    pub fn lists(&self) -> impl Iterator<Item = &'a str> + 'a {
+        self.memberships().map(|(list, _)| list)
+    }
+
+    pub fn in_list(&self, list: &str) -> bool {
+        self.catalog.get(list).is_some_and(|items| items.get(self.id).is_some())
+    }
+
+    // The record from the first list (in config order) that has the item
+    pub fn record(&self) -> Option<ItemRef<'a>> {
+        self.memberships().next().map(|(_, record)| record)
+    }
+
+    pub fn name(&self) -> Option<&'a str> {
+        self.record().map(|record| record.name())
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Catalog {
+    lists: Vec<(String, Cached<ItemIndex>)>,
+}
+
+impl Catalog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn load(cache_manager: &CacheManager, sources: &[CatalogSource]) -> Result<Self, CacheError> {
+        let mut catalog = Self::new();
+        for source in sources {
+            catalog.insert_index(&source.name, load_list(cache_manager, &source.path)?);
+        }
+        Ok(catalog)
+    }
+
+    // Replaces a list with the same name, otherwise appends it. The list counts as saved now.
+    pub fn insert(&mut self, name: &str, list: ItemList) {
+        let saved_at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        self.insert_cached(name, Cached { data: list, saved_at });
+    }
+
+    pub fn insert_cached(&mut self, name: &str, list: Cached<ItemList>) {
+        self.insert_index(name, Cached { data: ItemIndex::from(list.data), saved_at: list.saved_at });
+    }
+
+    pub fn insert_index(&mut self, name: &str, list: Cached<ItemIndex>) {
+        match self.lists.iter_mut().find(|(existing, _)| existing == name) {
+            Some((_, existing)) => *existing = list,
+            None => self.lists.push((name.to_string(), list)),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ItemIndex> {
+        self.lists.iter().find(|(existing, _)| existing == name).map(|(_, list)| &list.data)
+    }
+
+    // How long ago the list's cache file was written
+    pub fn age(&self, name: &str) -> Option<Duration> {
+        self.lists.iter().find(|(existing, _)| existing == name).map(|(_, list)| list.age())
+    }
+
+    pub fn list_names(&self) -> impl Iterator<Item = &str> {
+        self.lists.iter().map(|(name, _)| name.as_str())
+    }
+
+    pub fn lookup(&self, id: u32) -> CatalogEntry<'_> {
+        CatalogEntry { catalog: self, id }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list(items: &[(u32, &str)]) -> ItemList {
+        items.iter().map(|&(id, name)| (id, ItemRecord::named(name))).collect()
+    }
+
+    #[test]
+    fn lookup_reports_every_list_in_order() {
+        let mut catalog = Catalog::new();
+        catalog.insert("all", list(&[(1, "Sword"), (2, "Shield")]));
+        catalog.insert("bloody", list(&[(2, "Bloody Shield")]));
+        catalog.insert("watchlist", list(&[(1, "watched")]));
+
+        let entry = catalog.lookup(1);
+        assert_eq!(entry.lists().collect::<Vec<_>>(), vec!["all", "watchlist"]);
+        assert_eq!(entry.name(), Some("Sword"));
+        assert!(entry.in_list("watchlist") && !entry.in_list("bloody") && !entry.in_list("missing"));
+
+        assert_eq!(catalog.lookup(2).lists().collect::<Vec<_>>(), vec!["all", "bloody"]);
+        assert_eq!(catalog.lookup(3).memberships().count(), 0);
+        assert_eq!(catalog.lookup(3).name(), None);
+    }
+
+    #[test]
+    fn entries_expose_the_full_record() {
+        let mut items = list(&[(5, "Bow"), (20067, "Sword")]);
+        items.insert(7, ItemRecord { image_url: Some("axe.png".to_string()), ..ItemRecord::named("Axe") });
+        let mut catalog = Catalog::new();
+        catalog.insert("all", items);
+
+        let index = catalog.get("all").unwrap();
+        assert_eq!(index.iter().map(|(id, _)| id).collect::<Vec<_>>(), vec![5, 7, 20067]);
+        assert!(index.get(6).is_none());
+        assert_eq!(catalog.lookup(7).record().and_then(|item| item.image_url()), Some("axe.png"));
+    }
+
+    #[test]
+    fn load_reads_bincode_and_mapped_files_alike() {
+        let dir = std::env::temp_dir().join(format!("h278_catalog_load_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = |file: &str| dir.join(file).to_string_lossy().into_owned();
+        let manager = CacheManager::new(3600);
+        manager.save_cache(&list(&[(1, "Sword"), (2, "Shield")]), &path("all.bin")).unwrap();
+        let mut bloody = list(&[(2, "Bloody Shield")]);
+        bloody.insert(3, ItemRecord { min_stock: Some(4), ..ItemRecord::named("Bloody Axe") });
+        let saved_at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        mapped::write_mapped(&bloody, &path("bloody.map"), saved_at).unwrap();
+
+        let sources = [
+            CatalogSource { name: "all".to_string(), path: path("all.bin") },
+            CatalogSource { name: "bloody".to_string(), path: path("bloody.map") },
+        ];
+        let catalog = Catalog::load(&manager, &sources).unwrap();
+        assert!(!catalog.get("all").unwrap().is_mapped() && catalog.get("bloody").unwrap().is_mapped());
+        assert_eq!(catalog.lookup(2).lists().collect::<Vec<_>>(), vec!["all", "bloody"]);
+        assert_eq!(catalog.lookup(3).name(), Some("Bloody Axe"));
+        assert_eq!(catalog.lookup(3).record().unwrap().min_stock(), Some(4));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn insert_replaces_by_name() {
+        let mut catalog = Catalog::new();
+        catalog.insert("all", list(&[(1, "Old")]));
+        catalog.insert("all", list(&[(1, "New")]));
+        assert_eq!(catalog.list_names().collect::<Vec<_>>(), vec!["all"]);
+        assert_eq!(catalog.lookup(1).name(), Some("New"));
+        assert!(catalog.age("all").unwrap() < Duration::from_secs(60));
+        assert_eq!(catalog.age("bloody"), None);
+    }
+
+    #[test]
+    fn cache_config_reads_policy_names() {
+        let config: CacheConfig = serde_json::from_str(r#"{ "on_expired": "reject" }"#).unwrap();
+        assert!(matches!(config.on_expired, ExpiryPolicy::Reject));
+        assert_eq!(config.max_age_secs, 3600);
+        assert!(serde_json::from_str::<CacheConfig>(r#"{ "on_expired": "refresh" }"#).is_err());
+    }
+}
@@ -0,0 +1,241 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use crate::data::cache::{CacheError, Cached, One};
+
+// Catalog file layout, little endian:
+//
+//   0   magic (4 bytes)    "H2CT"
+//   4   version (u32)      schema of the body
+//   8   saved at (u64)     Unix seconds
+//   16  checksum (u32)     CRC32 of the body
+//   20  body length (u32)
+//   24  body               bincode of the version's item list
+//
+// Version 1 is the original headerless `(timestamp, bincode(One))` pair. It is still read, and
+// migrated in memory, but only the current version is written.
+pub const CATALOG_MAGIC: [u8; 4] = *b"H2CT";
+pub const CATALOG_VERSION: u32 = 2;
+pub const LEGACY_VERSION: u32 = 1;
+pub const CATALOG_HEADER_SIZE: usize = 24;
+
+// Everything a catalog knows about one item. New fields must go into a new schema version,
+// since bincode has no way to skip or default them in old files.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ItemRecord {
+    pub name: String,
+    pub category: Option<String>,
+    // Defaulted so JSON imports can leave it out
+    #[serde(default)]
+    pub tags: Vec<String>,
+    // Per-item alert thresholds, read by the `stock_at_least_item_min` and
+    // `price_at_most_item_max` rule conditions
+    pub min_stock: Option<u32>,
+    pub max_price: Option<u64>,
+    pub image_url: Option<String>,
+}
+
+impl ItemRecord {
+    pub fn named(name: impl Into<String>) -> Self {
+        ItemRecord { name: name.into(), ..Self::default() }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ItemList(BTreeMap<u32, ItemRecord>);
+
+impl From<BTreeMap<u32, ItemRecord>> for ItemList {
+    fn from(items: BTreeMap<u32, ItemRecord>) -> Self {
+        Self(items)
+    }
+}
+
+impl FromIterator<(u32, ItemRecord)> for ItemList {
+    fn from_iter<I: IntoIterator<Item = (u32, ItemRecord)>>(items: I) -> Self {
+        Self(items.into_iter().collect())
+    }
+}
+
+impl IntoIterator for ItemList {
+    type Item = (u32, ItemRecord);
+    type IntoIter = std::collections::btree_map::IntoIter<u32, ItemRecord>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl ItemList {
+    pub fn get(&self, id: u32) -> Option<&ItemRecord> {
+        self.0.get(&id)
+    }
+
+    pub fn insert(&mut self, id: u32, record: ItemRecord) -> Option<ItemRecord> {
+        self.0.insert(id, record)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    // In id order
+    pub fn iter(&self) -> impl Iterator<Item = (u32, &ItemRecord)> {
+        self.0.iter().map(|(id, record)| (*id, record))
+    }
+
+    // Version 1 lists map id strings to names. Ids that aren't `u32`s could never match a frame,
+    // so they are dropped. Spellings of the same id ("7" and "007") collide; the plain spelling
+    // wins, then the smallest string, so the result doesn't depend on the map's order.
+    pub fn from_legacy(list: One) -> (Self, LegacyMigration) {
+        let total = list.len();
+        let mut items: Vec<(u32, bool, String, String)> = list.into_iter()
+            .filter_map(|(text, name)| {
+                let id: u32 = text.trim().parse().ok()?;
+                Some((id, text != id.to_string(), text, name))
+            })
+            .collect();
+        let non_numeric = total - items.len();
+
+        items.sort_unstable_by(|a, b| (a.0, a.1, &a.2).cmp(&(b.0, b.1, &b.2)));
+        let numeric = items.len();
+        items.dedup_by_key(|&mut (id, ..)| id);
+        let migration = LegacyMigration { non_numeric, duplicate_ids: numeric - items.len() };
+
+        let list = items.into_iter().map(|(id, _, _, name)| (id, ItemRecord::named(name))).collect();
+        (list, migration)
+    }
+}
+
+// Entries a version 1 list lost on the way to the current schema
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LegacyMigration {
+    // Ids that don't parse as a `u32`
+    pub non_numeric: usize,
+    // Other spellings of an id that was already kept
+    pub duplicate_ids: usize,
+}
+
+pub fn encode(list: &ItemList, saved_at: u64) -> Result<Vec<u8>, CacheError> {
+    let body = bincode::serialize(list)?;
+    let len = u32::try_from(body.len())
+        .map_err(|_| CacheError::Corrupt(format!("catalog body of {} bytes is too large", body.len())))?;
+
+    let mut file = Vec::with_capacity(CATALOG_HEADER_SIZE + body.len());
+    file.extend_from_slice(&CATALOG_MAGIC);
+    file.extend_from_slice(&CATALOG_VERSION.to_le_bytes());
+    file.extend_from_slice(&saved_at.to_le_bytes());
+    file.extend_from_slice(&crc32fast::hash(&body).to_le_bytes());
+    file.extend_from_slice(&len.to_le_bytes());
+    file.extend_from_slice(&body);
+    Ok(file)
+}
+
+// Reads any version this build knows, returning the list in the current schema together with
+// the version the file was written in and what migrating it lost
+pub fn decode(bytes: &[u8]) -> Result<(Cached<ItemList>, u32, LegacyMigration), CacheError> {
+    if !bytes.starts_with(&CATALOG_MAGIC) {
+        let (saved_at, legacy): (u64, Vec<u8>) = bincode::deserialize(bytes)?;
+        let (data, migration) = ItemList::from_legacy(bincode::deserialize(&legacy)?);
+        return Ok((Cached { data, saved_at }, LEGACY_VERSION, migration));
+    }
+
+    if bytes.len() < CATALOG_HEADER_SIZE {
+        return Err(CacheError::Corrupt("catalog header is truncated".to_string()));
+    }
+    let u32_at = |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+    let version = u32_at(4);
+    let saved_at = u64::from_le_bytes(bytes[8..16].try_into().unwrap());
+    let checksum = u32_at(16);
+    let len = u32_at(20) as usize;
+
+    if version != CATALOG_VERSION {
+        return Err(CacheError::UnsupportedVersion(version));
+    }
+    let body = bytes.get(CATALOG_HEADER_SIZE..CATALOG_HEADER_SIZE + len)
+        .ok_or_else(|| CacheError::Corrupt(format!("catalog body is shorter than {} bytes", len)))?;
+    if crc32fast::hash(body) != checksum {
+        return Err(CacheError::Corrupt("catalog checksum does not match".to_string()));
+    }
+
+    Ok((Cached { data: bincode::deserialize(body)?, saved_at }, version, LegacyMigration::default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn sample() -> ItemList {
+        let mut list = ItemList::default();
+        list.insert(20067, ItemRecord {
+            name: "Sword".to_string(),
+            category: Some("weapon".to_string()),
+            tags: vec!["limited".to_string()],
+            min_stock: Some(2),
+            max_price: Some(500),
+            image_url: Some("https://example.com/20067.png".to_string()),
+        });
+        list.insert(20069, ItemRecord::named("Shield"));
+        list
+    }
+
+    #[test]
+    fn current_format_round_trips() {
+        let bytes = encode(&sample(), 1_700_000_000).unwrap();
+        let (cached, version, migration) = decode(&bytes).unwrap();
+        assert_eq!(version, CATALOG_VERSION);
+        assert_eq!(migration, LegacyMigration::default());
+        assert_eq!(cached.saved_at, 1_700_000_000);
+        assert_eq!(cached.data, sample());
+    }
+
+    #[test]
+    fn legacy_files_are_migrated() {
+        let legacy = One::from(HashMap::from([
+            ("20069".to_string(), "Shield".to_string()),
+            ("not-an-id".to_string(), "Odd".to_string()),
+        ]));
+        let bytes = bincode::serialize(&(42u64, bincode::serialize(&legacy).unwrap())).unwrap();
+
+        let (cached, version, migration) = decode(&bytes).unwrap();
+        assert_eq!(version, LEGACY_VERSION);
+        assert_eq!(migration, LegacyMigration { non_numeric: 1, duplicate_ids: 0 });
+        assert_eq!(cached.saved_at, 42);
+        assert_eq!(cached.data.iter().collect::<Vec<_>>(), vec![(20069, &ItemRecord::named("Shield"))]);
+    }
+
+    #[test]
+    fn legacy_id_collisions_keep_the_plain_spelling() {
+        let legacy = One::from(HashMap::from([
+            ("007".to_string(), "Padded".to_string()),
+            ("7".to_string(), "Plain".to_string()),
+            (" 7".to_string(), "Spaced".to_string()),
+            ("09".to_string(), "Nine".to_string()),
+            ("009".to_string(), "Also nine".to_string()),
+            ("x".to_string(), "Odd".to_string()),
+        ]));
+        let (list, migration) = ItemList::from_legacy(legacy);
+        assert_eq!(list.get(7), Some(&ItemRecord::named("Plain")));
+        assert_eq!(list.get(9), Some(&ItemRecord::named("Also nine")));
+        assert_eq!(migration, LegacyMigration { non_numeric: 1, duplicate_ids: 3 });
+    }
+
+    #[test]
+    fn unknown_versions_and_damage_are_reported() {
+        let mut bytes = encode(&sample(), 0).unwrap();
+
+        let mut future = bytes.clone();
+        future[4..8].copy_from_slice(&7u32.to_le_bytes());
+        let err = decode(&future).unwrap_err();
+        assert!(matches!(err, CacheError::UnsupportedVersion(7)));
+        assert!(err.to_string().contains("version 7"), "{}", err);
+
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xFF;
+        assert!(matches!(decode(&bytes), Err(CacheError::Corrupt(_))));
+        assert!(matches!(decode(&bytes[..30]), Err(CacheError::Corrupt(_))));
+    }
+}
@@ -0,0 +1,533 @@
+use serde::Deserialize;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use crate::service::reader_service::StockRecord;
+
+// Stock history is an append-only log split into segments named by the receive time (Unix
+// millis) of their first frame. Each segment starts with the magic and version, followed by one
+// entry per frame, little endian:
+//
+//   body length (u32), CRC32 of the body (u32), then the body:
+//   client id (u32), received at (u64 millis), record count (u32),
+//   and per record id (u32), stock (u32), price (u64), timestamp (u64)
+//
+// A crash can leave a torn entry at the end of the newest segment, or the segment without its
+// header; opening the store cuts the entry off and rewrites the header. A failed write is cut off
+// the same way straight away.
+const SEGMENT_MAGIC: [u8; 4] = *b"H2HL";
+const SEGMENT_VERSION: u32 = 1;
+const SEGMENT_HEADER_SIZE: u64 = 8;
+const ENTRY_HEADER_SIZE: usize = 8;
+const FRAME_HEADER_SIZE: usize = 16;
+const RECORD_SIZE: usize = 24;
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct HistoryConfig {
+    pub enabled: bool,
+    pub dir: String,
+    // A new segment is started once the current one passes this size
+    pub segment_bytes: u64,
+    // Entries are buffered; this bounds how far queries from another process can lag behind
+    pub flush_interval_ms: u64,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            dir: "history".to_string(),
+            segment_bytes: 64 * 1024 * 1024,
+            flush_interval_ms: 1000,
+        }
+    }
+}
+
+// One frame's records as they were received
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryFrame {
+    pub client_id: u32,
+    // Unix millis
+    pub received_at: u64,
+    pub records: Vec<StockRecord>,
+}
+
+// One record of an item, with where and when it arrived
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Observation {
+    pub client_id: u32,
+    pub received_at: u64,
+    pub stock: u32,
+    pub price: u64,
+    pub timestamp: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RestockStats {
+    pub observations: usize,
+    // Times a client reported more stock than it did the time before
+    pub restocks: usize,
+    pub first_restock: Option<u64>,
+    pub last_restock: Option<u64>,
+}
+
+impl RestockStats {
+    // Average time between restocks, once there have been two. Receive times come from the
+    // producer's clock and can step backwards, which counts as no time passing.
+    pub fn mean_interval(&self) -> Option<Duration> {
+        match (self.first_restock, self.last_restock) {
+            (Some(first), Some(last)) if self.restocks > 1 => {
+                Some(Duration::from_millis(last.saturating_sub(first) / (self.restocks as u64 - 1)))
+            }
+            _ => None,
+        }
+    }
+}
+
+pub struct HistoryStore {
+    dir: PathBuf,
+    config: HistoryConfig,
+    // None until the first frame after opening, so segments are named by a real receive time
+    segment: Option<(BufWriter<File>, u64)>,
+    last_flush: Instant,
+}
+
+impl HistoryStore {
+    pub fn open(config: &HistoryConfig) -> io::Result<Self> {
+        let dir = PathBuf::from(&config.dir);
+        fs::create_dir_all(&dir)?;
+
+        let mut segment = None;
+        if let Some((_, path)) = segments(&dir)?.pop() {
+            let mut file = OpenOptions::new().read(true).write(true).open(&path)?;
+            let valid = if file.metadata()?.len() < SEGMENT_HEADER_SIZE {
+                // A crash in `rotate` between creating the segment and writing its header
+                let mut bytes = Vec::new();
+                file.read_to_end(&mut bytes)?;
+                if !segment_header().starts_with(&bytes) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{}: not a stock history segment", path.display()),
+                    ));
+                }
+                eprintln!("Rewriting the torn header of {}", path.display());
+                file.set_len(0)?;
+                file.seek(SeekFrom::Start(0))?;
+                file.write_all(&segment_header())?;
+                SEGMENT_HEADER_SIZE
+            } else {
+                let valid = recover(&mut file)?;
+                if valid < file.metadata()?.len() {
+                    eprintln!("Truncating torn history entry at byte {} of {}", valid, path.display());
+                    file.set_len(valid)?;
+                }
+                valid
+            };
+            file.seek(SeekFrom::End(0))?;
+            segment = Some((BufWriter::new(file), valid));
+        }
+
+        Ok(HistoryStore { dir, config: config.clone(), segment, last_flush: Instant::now() })
+    }
+
+    pub fn append(&mut self, client_id: u32, received_at: u64, records: &[StockRecord]) -> io::Result<()> {
+        let mut body = Vec::with_capacity(FRAME_HEADER_SIZE + records.len() * RECORD_SIZE);
+        body.extend_from_slice(&client_id.to_le_bytes());
+        body.extend_from_slice(&received_at.to_le_bytes());
+        body.extend_from_slice(&(records.len() as u32).to_le_bytes());
+        for record in records {
+            body.extend_from_slice(&record.id.to_le_bytes());
+            body.extend_from_slice(&record.stock.to_le_bytes());
+            body.extend_from_slice(&record.price.to_le_bytes());
+            body.extend_from_slice(&record.timestamp.to_le_bytes());
+        }
+
+        let full = self.segment.as_ref().is_none_or(|(_, len)| *len >= self.config.segment_bytes);
+        if full {
+            self.rotate(received_at)?;
+        }
+        let (writer, len) = self.segment.as_mut().unwrap();
+        let written = writer.write_all(&(body.len() as u32).to_le_bytes())
+            .and_then(|()| writer.write_all(&crc32fast::hash(&body).to_le_bytes()))
+            .and_then(|()| writer.write_all(&body));
+        if let Err(e) = written {
+            self.cut_torn_tail();
+            return Err(e);
+        }
+        *len += (ENTRY_HEADER_SIZE + body.len()) as u64;
+
+        if self.last_flush.elapsed() >= Duration::from_millis(self.config.flush_interval_ms) {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn rotate(&mut self, received_at: u64) -> io::Result<()> {
+        self.flush()?;
+        // Two segments started in the same millisecond get the next free name
+        let mut start = received_at;
+        while segment_path(&self.dir, start).exists() {
+            start += 1;
+        }
+        let path = segment_path(&self.dir, start);
+        let mut file = OpenOptions::new().read(true).write(true).create_new(true).open(&path)?;
+        if let Err(e) = file.write_all(&segment_header()) {
+            let _ = fs::remove_file(&path);
+            return Err(e);
+        }
+        self.segment = Some((BufWriter::new(file), SEGMENT_HEADER_SIZE));
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.last_flush = Instant::now();
+        let Some((writer, _)) = &mut self.segment else {
+            return Ok(());
+        };
+        let flushed = writer.flush();
+        if flushed.is_err() {
+            self.cut_torn_tail();
+        }
+        flushed
+    }
+
+    // After a failed write the segment may end part way through an entry, and anything appended
+    // after it would be unreadable. Drops whatever is still buffered and cuts the file back to its
+    // last intact entry. If even that fails, the next append starts a new segment.
+    fn cut_torn_tail(&mut self) {
+        let Some((writer, _)) = self.segment.take() else {
+            return;
+        };
+        let (mut file, _) = writer.into_parts();
+        let cut = file.seek(SeekFrom::Start(0))
+            .and_then(|_| recover(&mut file))
+            .and_then(|valid| {
+                file.set_len(valid)?;
+                file.seek(SeekFrom::End(0))?;
+                Ok(valid)
+            });
+        match cut {
+            Ok(valid) => self.segment = Some((BufWriter::new(file), valid)),
+            Err(e) => eprintln!("Starting a new history segment, cutting off a failed write failed: {}", e),
+        }
+    }
+
+    // Reads what has been written so far, including buffered entries
+    pub fn reader(&mut self) -> io::Result<HistoryReader> {
+        self.flush()?;
+        Ok(HistoryReader::new(&self.dir))
+    }
+}
+
+impl Drop for HistoryStore {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            eprintln!("Failed to flush stock history: {}", e);
+        }
+    }
+}
+
+fn segment_header() -> [u8; SEGMENT_HEADER_SIZE as usize] {
+    let mut header = [0; SEGMENT_HEADER_SIZE as usize];
+    header[..4].copy_from_slice(&SEGMENT_MAGIC);
+    header[4..].copy_from_slice(&SEGMENT_VERSION.to_le_bytes());
+    header
+}
+
+fn segment_path(dir: &Path, start: u64) -> PathBuf {
+    dir.join(format!("{:020}.log", start))
+}
+
+// Segments in time order, with the receive time of their first frame
+fn segments(dir: &Path) -> io::Result<Vec<(u64, PathBuf)>> {
+    let mut segments = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let start = path.file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_suffix(".log"))
+            .and_then(|start| start.parse().ok());
+        if let Some(start) = start {
+            segments.push((start, path));
+        }
+    }
+    segments.sort();
+    Ok(segments)
+}
+
+// The length of the segment's intact prefix
+fn recover(file: &mut File) -> io::Result<u64> {
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes)?;
+    let mut reader = SegmentReader::new(&bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    while let Some(Ok(_)) = reader.next() {}
+    Ok(reader.offset as u64)
+}
+
+struct SegmentReader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> SegmentReader<'a> {
+    fn new(bytes: &'a [u8]) -> Result<Self, String> {
+        if !bytes.starts_with(&SEGMENT_MAGIC) || bytes.len() < SEGMENT_HEADER_SIZE as usize {
+            return Err("not a stock history segment".to_string());
+        }
+        let version = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+        if version != SEGMENT_VERSION {
+            return Err(format!("unsupported stock history version {}", version));
+        }
+        Ok(SegmentReader { bytes, offset: SEGMENT_HEADER_SIZE as usize })
+    }
+}
+
+impl Iterator for SegmentReader<'_> {
+    // A torn or damaged entry ends the segment
+    type Item = Result<HistoryFrame, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = &self.bytes[self.offset..];
+        if rest.is_empty() {
+            return None;
+        }
+        let damaged = |reason: &str| Some(Err(format!("{} at byte {}", reason, self.offset)));
+        if rest.len() < ENTRY_HEADER_SIZE {
+            return damaged("truncated entry header");
+        }
+        let len = u32::from_le_bytes(rest[0..4].try_into().unwrap()) as usize;
+        let checksum = u32::from_le_bytes(rest[4..8].try_into().unwrap());
+        let Some(body) = rest.get(ENTRY_HEADER_SIZE..ENTRY_HEADER_SIZE + len) else {
+            return damaged("truncated entry");
+        };
+        if crc32fast::hash(body) != checksum || len < FRAME_HEADER_SIZE {
+            return damaged("damaged entry");
+        }
+
+        let u32_at = |at: usize| u32::from_le_bytes(body[at..at + 4].try_into().unwrap());
+        let u64_at = |at: usize| u64::from_le_bytes(body[at..at + 8].try_into().unwrap());
+        let count = u32_at(12) as usize;
+        if len != FRAME_HEADER_SIZE + count * RECORD_SIZE {
+            return damaged("damaged entry");
+        }
+        let records = (0..count)
+            .map(|index| {
+                let at = FRAME_HEADER_SIZE + index * RECORD_SIZE;
+                StockRecord { id: u32_at(at), stock: u32_at(at + 4), price: u64_at(at + 8), timestamp: u64_at(at + 16) }
+            })
+            .collect();
+
+        self.offset += ENTRY_HEADER_SIZE + len;
+        Some(Ok(HistoryFrame { client_id: u32_at(0), received_at: u64_at(4), records }))
+    }
+}
+
+// Queries over a history directory. Each one scans the segments that can hold matching frames,
+// so it sees everything flushed before it started.
+pub struct HistoryReader {
+    dir: PathBuf,
+}
+
+impl HistoryReader {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        HistoryReader { dir: dir.into() }
+    }
+
+    // Every frame received in `since..until` (Unix millis), in order
+    pub fn frames(&self, since: u64, until: u64) -> io::Result<Vec<HistoryFrame>> {
+        let segments = segments(&self.dir)?;
+        let mut frames = Vec::new();
+        for (index, (start, path)) in segments.iter().enumerate() {
+            // A segment ends where the next one starts
+            let ends_before = segments.get(index + 1).is_some_and(|(next, _)| *next <= since);
+            if ends_before || *start >= until {
+                continue;
+            }
+
+            let bytes = fs::read(path)?;
+            // The writer may not have written the newest segment's header yet
+            if bytes.len() < SEGMENT_HEADER_SIZE as usize && segment_header().starts_with(&bytes) {
+                continue;
+            }
+            let reader = SegmentReader::new(&bytes)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))?;
+            for frame in reader {
+                match frame {
+                    Ok(frame) if (since..until).contains(&frame.received_at) => frames.push(frame),
+                    Ok(_) => {}
+                    // The writer may be part way through the newest segment
+                    Err(_) => break,
+                }
+            }
+        }
+        Ok(frames)
+    }
+
+    pub fn item_history(&self, id: u32, since: u64, until: u64) -> io::Result<Vec<Observation>> {
+        let mut observations = Vec::new();
+        for frame in self.frames(since, until)? {
+            for record in frame.records.iter().filter(|record| record.id == id) {
+                observations.push(Observation {
+                    client_id: frame.client_id,
+                    received_at: frame.received_at,
+                    stock: record.stock,
+                    price: record.price,
+                    timestamp: record.timestamp,
+                });
+            }
+        }
+        Ok(observations)
+    }
+
+    // The most recent observation of the item with stock
+    pub fn last_seen(&self, id: u32) -> io::Result<Option<Observation>> {
+        Ok(self.item_history(id, 0, u64::MAX)?.into_iter().rev().find(|observation| observation.stock > 0))
+    }
+
+    pub fn restocks(&self, id: u32, since: u64, until: u64) -> io::Result<RestockStats> {
+        let observations = self.item_history(id, since, until)?;
+        let mut stats = RestockStats { observations: observations.len(), ..RestockStats::default() };
+        // Stock is tracked per client, since each one reports its own
+        let mut previous: Vec<(u32, u32)> = Vec::new();
+        for observation in observations {
+            let last = match previous.iter_mut().find(|(client_id, _)| *client_id == observation.client_id) {
+                Some((_, stock)) => std::mem::replace(stock, observation.stock),
+                None => {
+                    previous.push((observation.client_id, observation.stock));
+                    continue;
+                }
+            };
+            if observation.stock > last {
+                stats.restocks += 1;
+                stats.first_restock.get_or_insert(observation.received_at);
+                stats.last_restock = Some(observation.received_at);
+            }
+        }
+        Ok(stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(test: &str, segment_bytes: u64) -> HistoryConfig {
+        let dir = std::env::temp_dir().join(format!("h278_history_{}_{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        HistoryConfig { enabled: true, dir: dir.to_string_lossy().into_owned(), segment_bytes, flush_interval_ms: 0 }
+    }
+
+    fn record(id: u32, stock: u32) -> StockRecord {
+        StockRecord { id, stock, price: 100, timestamp: 1 }
+    }
+
+    #[test]
+    fn queries_span_segments_and_time_ranges() {
+        let config = config("queries", 64);
+        let mut store = HistoryStore::open(&config).unwrap();
+        for (at, stock) in [(1000, 0), (2000, 3), (3000, 1), (4000, 5), (5000, 0)] {
+            store.append(7, at, &[record(1, stock), record(2, 9)]).unwrap();
+        }
+        let reader = store.reader().unwrap();
+        assert!(segments(Path::new(&config.dir)).unwrap().len() > 1);
+
+        let history = reader.item_history(1, 0, u64::MAX).unwrap();
+        assert_eq!(history.iter().map(|o| o.stock).collect::<Vec<_>>(), vec![0, 3, 1, 5, 0]);
+        let window = reader.item_history(1, 2000, 4000).unwrap();
+        assert_eq!(window.iter().map(|o| o.received_at).collect::<Vec<_>>(), vec![2000, 3000]);
+
+        assert_eq!(reader.last_seen(1).unwrap().map(|o| o.received_at), Some(4000));
+        assert_eq!(reader.last_seen(3).unwrap(), None);
+
+        let stats = reader.restocks(1, 0, u64::MAX).unwrap();
+        assert_eq!((stats.observations, stats.restocks), (5, 2));
+        assert_eq!(stats.mean_interval(), Some(Duration::from_millis(2000)));
+
+        fs::remove_dir_all(&config.dir).unwrap();
+    }
+
+    #[test]
+    fn restocks_survive_receive_times_going_backwards() {
+        let config = config("backwards", u64::MAX);
+        let mut store = HistoryStore::open(&config).unwrap();
+        for (at, stock) in [(5000, 0), (6000, 3), (2000, 0), (3000, 4)] {
+            store.append(1, at, &[record(1, stock)]).unwrap();
+        }
+
+        let stats = store.reader().unwrap().restocks(1, 0, u64::MAX).unwrap();
+        assert_eq!((stats.first_restock, stats.last_restock), (Some(6000), Some(3000)));
+        assert_eq!(stats.mean_interval(), Some(Duration::ZERO));
+
+        fs::remove_dir_all(&config.dir).unwrap();
+    }
+
+    #[test]
+    fn reopening_cuts_off_a_torn_entry() {
+        let config = config("torn", u64::MAX);
+        {
+            let mut store = HistoryStore::open(&config).unwrap();
+            store.append(1, 1000, &[record(1, 2)]).unwrap();
+            store.append(1, 2000, &[record(1, 4)]).unwrap();
+        }
+        let (_, path) = segments(Path::new(&config.dir)).unwrap().pop().unwrap();
+        let len = fs::metadata(&path).unwrap().len();
+        OpenOptions::new().write(true).open(&path).unwrap().set_len(len - 5).unwrap();
+
+        // The reader stops at the damage; the store drops it and carries on after the intact part
+        assert_eq!(HistoryReader::new(&config.dir).item_history(1, 0, u64::MAX).unwrap().len(), 1);
+        let mut store = HistoryStore::open(&config).unwrap();
+        store.append(1, 3000, &[record(1, 6)]).unwrap();
+        let history = store.reader().unwrap().item_history(1, 0, u64::MAX).unwrap();
+        assert_eq!(history.iter().map(|o| o.stock).collect::<Vec<_>>(), vec![2, 6]);
+
+        fs::remove_dir_all(&config.dir).unwrap();
+    }
+
+    #[test]
+    fn reopening_repairs_a_segment_without_its_header() {
+        for partial in [&b""[..], &b"H2H"[..]] {
+            let config = config("headerless", u64::MAX);
+            {
+                let mut store = HistoryStore::open(&config).unwrap();
+                store.append(1, 1000, &[record(1, 2)]).unwrap();
+            }
+            fs::write(segment_path(Path::new(&config.dir), 2000), partial).unwrap();
+            assert_eq!(HistoryReader::new(&config.dir).item_history(1, 0, u64::MAX).unwrap().len(), 1);
+
+            let mut store = HistoryStore::open(&config).unwrap();
+            store.append(1, 3000, &[record(1, 6)]).unwrap();
+            let history = store.reader().unwrap().item_history(1, 0, u64::MAX).unwrap();
+            assert_eq!(history.iter().map(|o| o.stock).collect::<Vec<_>>(), vec![2, 6]);
+
+            fs::remove_dir_all(&config.dir).unwrap();
+        }
+
+        let config = config("foreign", u64::MAX);
+        fs::create_dir_all(&config.dir).unwrap();
+        fs::write(segment_path(Path::new(&config.dir), 1000), b"oops").unwrap();
+        assert_eq!(HistoryStore::open(&config).err().unwrap().kind(), io::ErrorKind::InvalidData);
+        fs::remove_dir_all(&config.dir).unwrap();
+    }
+
+    #[test]
+    fn a_failed_write_is_cut_off_before_the_next_entry() {
+        let config = config("failed_write", u64::MAX);
+        let mut store = HistoryStore::open(&config).unwrap();
+        store.append(1, 1000, &[record(1, 2)]).unwrap();
+
+        // What a write that failed part way through an entry leaves behind
+        let (writer, _) = store.segment.as_mut().unwrap();
+        writer.write_all(&[40, 0, 0, 0, 1, 2]).unwrap();
+        writer.flush().unwrap();
+        store.cut_torn_tail();
+
+        store.append(1, 2000, &[record(1, 4)]).unwrap();
+        let history = store.reader().unwrap().item_history(1, 0, u64::MAX).unwrap();
+        assert_eq!(history.iter().map(|o| o.stock).collect::<Vec<_>>(), vec![2, 4]);
+
+        drop(store);
+        fs::remove_dir_all(&config.dir).unwrap();
+    }
+}
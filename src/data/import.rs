@@ -0,0 +1,240 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error as StdError;
+use std::fmt;
+use std::path::Path;
+use crate::data::format::{ItemList, ItemRecord};
+
+// Source formats for item lists. CSV and TSV are `id,name` rows with an optional header; JSON is
+// either an `{"id": "name"}` object or an array of `{"id": .., "name": ..}` records, which may also
+// carry the other `ItemRecord` fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListFormat {
+    Csv,
+    Tsv,
+    Json,
+}
+
+impl ListFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "csv" => Some(ListFormat::Csv),
+            "tsv" | "tab" => Some(ListFormat::Tsv),
+            "json" => Some(ListFormat::Json),
+            _ => None,
+        }
+    }
+
+    pub fn from_path(path: &Path) -> Option<Self> {
+        path.extension().and_then(|ext| ext.to_str()).and_then(Self::from_name)
+    }
+}
+
+#[derive(Debug)]
+pub enum ImportError {
+    JsonError(serde_json::Error),
+    // 1-based line of a CSV/TSV row, or 1-based record of a JSON array
+    InvalidRow { row: usize, message: String },
+    DuplicateId { row: usize, id: String },
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImportError::JsonError(e) => write!(f, "JSON error: {}", e),
+            ImportError::InvalidRow { row, message } => write!(f, "Row {}: {}", row, message),
+            ImportError::DuplicateId { row, id } => write!(f, "Row {}: duplicate id {}", row, id),
+        }
+    }
+}
+
+impl StdError for ImportError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            ImportError::JsonError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<serde_json::Error> for ImportError {
+    fn from(err: serde_json::Error) -> Self {
+        ImportError::JsonError(err)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonId {
+    Number(u64),
+    Text(String),
+}
+
+impl JsonId {
+    fn into_string(self) -> String {
+        match self {
+            JsonId::Number(id) => id.to_string(),
+            JsonId::Text(id) => id,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct JsonRecord {
+    id: JsonId,
+    #[serde(flatten)]
+    item: ItemRecord,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonList {
+    Map(BTreeMap<String, String>),
+    Records(Vec<JsonRecord>),
+}
+
+pub fn parse_list(text: &str, format: ListFormat) -> Result<ItemList, ImportError> {
+    let rows: Vec<(usize, String, ItemRecord)> = match format {
+        ListFormat::Csv => parse_delimited(text, ',')?,
+        ListFormat::Tsv => parse_delimited(text, '\t')?,
+        ListFormat::Json => match serde_json::from_str(text)? {
+            JsonList::Map(items) => items.into_iter()
+                .enumerate()
+                .map(|(n, (id, name))| (n + 1, id, ItemRecord::named(name)))
+                .collect(),
+            JsonList::Records(records) => records.into_iter()
+                .enumerate()
+                .map(|(n, record)| (n + 1, record.id.into_string(), record.item))
+                .collect(),
+        },
+    };
+
+    let mut items = ItemList::default();
+    for (row, id, item) in rows {
+        if id.is_empty() {
+            return Err(ImportError::InvalidRow { row, message: "empty id".to_string() });
+        }
+        // Frames carry `u32` ids, so anything else could never match
+        let Ok(numeric) = id.parse::<u32>() else {
+            return Err(ImportError::InvalidRow { row, message: format!("id '{}' is not a u32", id) });
+        };
+        if items.insert(numeric, item).is_some() {
+            return Err(ImportError::DuplicateId { row, id });
+        }
+    }
+    Ok(items)
+}
+
+// `id<sep>name` per line. Fields may be double quoted (with `""` for a quote) so names can
+// contain the separator. Blank lines are skipped, as is a leading `id,name` header.
+fn parse_delimited(text: &str, separator: char) -> Result<Vec<(usize, String, ItemRecord)>, ImportError> {
+    let mut rows = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let row = index + 1;
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let fields = split_fields(line, separator)
+            .map_err(|message| ImportError::InvalidRow { row, message })?;
+        let [id, name]: [String; 2] = fields.try_into().map_err(|fields: Vec<String>| ImportError::InvalidRow {
+            row,
+            message: format!("expected 2 fields, found {}", fields.len()),
+        })?;
+
+        if rows.is_empty() && id.eq_ignore_ascii_case("id") {
+            continue;
+        }
+        rows.push((row, id.trim().to_string(), ItemRecord::named(name)));
+    }
+    Ok(rows)
+}
+
+fn split_fields(line: &str, separator: char) -> Result<Vec<String>, String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut chars = line.chars().peekable();
+    let mut quoted = false;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.is_empty() => quoted = true,
+            c if c == separator && !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    if quoted {
+        return Err("unterminated quote".to_string());
+    }
+    fields.push(field);
+    Ok(fields)
+}
+
+#[derive(Serialize)]
+struct JsonRecordOut<'a> {
+    id: u32,
+    #[serde(flatten)]
+    item: &'a ItemRecord,
+}
+
+// Pretty JSON array of records sorted by id, which `parse_list` reads back
+pub fn to_json(list: &ItemList) -> String {
+    let records: Vec<JsonRecordOut> = list.iter().map(|(id, item)| JsonRecordOut { id, item }).collect();
+    serde_json::to_string_pretty(&records).expect("item records always serialize")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delimited_lists_handle_headers_and_quotes() {
+        let csv = "id,name\r\n20067,\"Sword, Bloody\"\n\n20069,\"The \"\"Shield\"\"\"\n";
+        let list = parse_list(csv, ListFormat::Csv).unwrap();
+        assert_eq!(list.len(), 2);
+        assert_eq!(list.get(20067).unwrap().name, "Sword, Bloody");
+        assert_eq!(list.get(20069).unwrap().name, "The \"Shield\"");
+
+        let tsv = parse_list("1\tSword, with comma\n", ListFormat::Tsv).unwrap();
+        assert_eq!(tsv.get(1).unwrap().name, "Sword, with comma");
+    }
+
+    #[test]
+    fn json_lists_come_as_objects_or_records() {
+        let map = parse_list(r#"{ "1": "Sword" }"#, ListFormat::Json).unwrap();
+        let records = parse_list(
+            r#"[{ "id": 1, "name": "Sword" }, { "id": "2", "name": "Axe", "category": "weapon", "tags": ["rare"], "max_price": 900 }]"#,
+            ListFormat::Json,
+        ).unwrap();
+        assert_eq!(map.get(1), Some(&ItemRecord::named("Sword")));
+        assert_eq!(records.get(1), Some(&ItemRecord::named("Sword")));
+        let axe = records.get(2).unwrap();
+        assert_eq!((axe.category.as_deref(), axe.tags.as_slice(), axe.max_price), (Some("weapon"), &["rare".to_string()][..], Some(900)));
+
+        assert_eq!(parse_list(&to_json(&records), ListFormat::Json).unwrap(), records);
+    }
+
+    #[test]
+    fn bad_rows_are_reported_by_number() {
+        let err = parse_list("1,Sword\n2\n", ListFormat::Csv).unwrap_err();
+        assert!(matches!(err, ImportError::InvalidRow { row: 2, .. }), "{}", err);
+
+        let err = parse_list("1,Sword\n1,Shield\n", ListFormat::Csv).unwrap_err();
+        assert!(matches!(err, ImportError::DuplicateId { row: 2, .. }), "{}", err);
+
+        assert!(parse_list("1,\"Sword\n", ListFormat::Csv).is_err());
+
+        // "7" and "007" are the same item
+        let err = parse_list("7,Axe\n007,Axe\n", ListFormat::Csv).unwrap_err();
+        assert!(matches!(err, ImportError::DuplicateId { row: 2, .. }), "{}", err);
+
+        let err = parse_list("abc,Odd\n", ListFormat::Csv).unwrap_err();
+        assert!(matches!(err, ImportError::InvalidRow { row: 1, .. }), "{}", err);
+    }
+}
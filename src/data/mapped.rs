@@ -0,0 +1,464 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use crate::data::cache::CacheError;
+use crate::data::format::{ItemList, ItemRecord};
+
+// Memory-mapped catalog layout, little endian, read in place without deserializing:
+//
+//   0   magic (4 bytes)     "H2CM"
+//   4   version (u32)
+//   8   saved at (u64)      Unix seconds
+//   16  item count (u32)
+//   20  string table length (u32)
+//   24  checksum (u32)      CRC32 of everything after the header
+//   28  reserved (u32)
+//   32  ids                 count u32s, sorted ascending
+//   ..  entries             count entries of ENTRY_SIZE bytes, in id order
+//   ..  string table        UTF-8
+//
+// An entry is four (offset, length) string references (name, category, image URL, tags joined by
+// TAG_SEPARATOR), then min stock (u32), 4 reserved bytes and max price (u64). An offset of NONE
+// marks an absent string, and a threshold of all ones an absent threshold.
+pub const MAPPED_MAGIC: [u8; 4] = *b"H2CM";
+pub const MAPPED_VERSION: u32 = 1;
+const HEADER_SIZE: usize = 32;
+const ENTRY_SIZE: usize = 48;
+const NONE: u32 = u32::MAX;
+const TAG_SEPARATOR: char = '\u{1f}';
+
+// The mapping is read only and lives as long as the catalog. Files must be replaced by rename
+// (as `write_mapped` does), never rewritten in place, or readers would see the change under them.
+#[cfg(unix)]
+struct Mapping {
+    ptr: *const u8,
+    len: usize,
+}
+
+#[cfg(unix)]
+unsafe impl Send for Mapping {}
+#[cfg(unix)]
+unsafe impl Sync for Mapping {}
+
+#[cfg(unix)]
+impl Mapping {
+    fn new(file: &File, len: usize) -> io::Result<Self> {
+        use std::os::unix::io::AsRawFd;
+
+        let ptr = unsafe {
+            libc::mmap(std::ptr::null_mut(), len, libc::PROT_READ, libc::MAP_SHARED, file.as_raw_fd(), 0)
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Mapping { ptr: ptr as *const u8, len })
+    }
+
+    fn bytes(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ptr as *mut libc::c_void, self.len) };
+    }
+}
+
+// Sorted ids plus a string table, looked up straight out of the mapped file. The page cache backs
+// the mapping, so every process opening the same file shares one copy of it.
+pub struct MappedCatalog {
+    #[cfg(unix)]
+    bytes: Mapping,
+    // Elsewhere the file is read into memory; the layout is still used in place
+    #[cfg(not(unix))]
+    bytes: Vec<u8>,
+    saved_at: u64,
+    count: usize,
+    strings_start: usize,
+}
+
+impl fmt::Debug for MappedCatalog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MappedCatalog")
+            .field("items", &self.count)
+            .field("saved_at", &self.saved_at)
+            .finish()
+    }
+}
+
+impl MappedCatalog {
+    // Checks the header and that every string reference is in bounds, but not the checksum,
+    // which would mean reading the whole file; `verify` does that.
+    pub fn open(path: &str) -> Result<Self, CacheError> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len() as usize;
+        if len < HEADER_SIZE {
+            return Err(CacheError::Corrupt("mapped catalog header is truncated".to_string()));
+        }
+
+        #[cfg(unix)]
+        let bytes = Mapping::new(&file, len)?;
+        #[cfg(not(unix))]
+        let bytes = {
+            let mut bytes = Vec::with_capacity(len);
+            (&file).read_to_end(&mut bytes)?;
+            bytes
+        };
+
+        let mut catalog = MappedCatalog { bytes, saved_at: 0, count: 0, strings_start: 0 };
+        catalog.validate()?;
+        Ok(catalog)
+    }
+
+    fn bytes(&self) -> &[u8] {
+        #[cfg(unix)]
+        return self.bytes.bytes();
+        #[cfg(not(unix))]
+        return &self.bytes;
+    }
+
+    fn validate(&mut self) -> Result<(), CacheError> {
+        let bytes = self.bytes();
+        if !bytes.starts_with(&MAPPED_MAGIC) {
+            return Err(CacheError::Corrupt("not a mapped catalog".to_string()));
+        }
+        let version = read_u32(bytes, 4);
+        if version != MAPPED_VERSION {
+            return Err(CacheError::UnsupportedVersion(version));
+        }
+        let saved_at = read_u64(bytes, 8);
+        let count = read_u32(bytes, 16) as usize;
+        let strings_len = read_u32(bytes, 20) as usize;
+
+        let strings_start = HEADER_SIZE + count * (4 + ENTRY_SIZE);
+        if bytes.len() as u64 != strings_start as u64 + strings_len as u64 {
+            return Err(CacheError::Corrupt(format!(
+                "mapped catalog is {} bytes, its header describes {}",
+                bytes.len(), strings_start + strings_len,
+            )));
+        }
+        let strings = std::str::from_utf8(&bytes[strings_start..])
+            .map_err(|_| CacheError::Corrupt("mapped catalog strings are not UTF-8".to_string()))?;
+
+        let mut previous = None;
+        for index in 0..count {
+            let id = read_u32(bytes, HEADER_SIZE + index * 4);
+            if previous.is_some_and(|previous| previous >= id) {
+                return Err(CacheError::Corrupt(format!("mapped catalog ids are out of order at {}", id)));
+            }
+            previous = Some(id);
+
+            let entry = HEADER_SIZE + count * 4 + index * ENTRY_SIZE;
+            for field in 0..4 {
+                let (offset, len) = (read_u32(bytes, entry + field * 8), read_u32(bytes, entry + field * 8 + 4));
+                if offset != NONE && strings.get(offset as usize..offset as usize + len as usize).is_none() {
+                    return Err(CacheError::Corrupt(format!("mapped catalog string for id {} is out of bounds", id)));
+                }
+            }
+        }
+
+        self.saved_at = saved_at;
+        self.count = count;
+        self.strings_start = strings_start;
+        Ok(())
+    }
+
+    // Compares the stored checksum with the file contents
+    pub fn verify(&self) -> Result<(), CacheError> {
+        let bytes = self.bytes();
+        if crc32fast::hash(&bytes[HEADER_SIZE..]) != read_u32(bytes, 24) {
+            return Err(CacheError::Corrupt("mapped catalog checksum does not match".to_string()));
+        }
+        Ok(())
+    }
+
+    pub fn saved_at(&self) -> u64 {
+        self.saved_at
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    // On little-endian targets the id array is used as is; the mapping is page aligned and the
+    // array starts 32 bytes in
+    fn ids(&self) -> Option<&[u32]> {
+        if cfg!(target_endian = "big") {
+            return None;
+        }
+        let bytes = &self.bytes()[HEADER_SIZE..HEADER_SIZE + self.count * 4];
+        match unsafe { bytes.align_to::<u32>() } {
+            (&[], ids, &[]) => Some(ids),
+            _ => None,
+        }
+    }
+
+    fn id_at(&self, index: usize) -> u32 {
+        read_u32(self.bytes(), HEADER_SIZE + index * 4)
+    }
+
+    pub fn get(&self, id: u32) -> Option<MappedItem<'_>> {
+        let index = match self.ids() {
+            Some(ids) => ids.binary_search(&id).ok()?,
+            None => {
+                let (mut low, mut high) = (0, self.count);
+                loop {
+                    if low >= high {
+                        return None;
+                    }
+                    let mid = low + (high - low) / 2;
+                    match self.id_at(mid).cmp(&id) {
+                        std::cmp::Ordering::Less => low = mid + 1,
+                        std::cmp::Ordering::Greater => high = mid,
+                        std::cmp::Ordering::Equal => break mid,
+                    }
+                }
+            }
+        };
+        Some(MappedItem { catalog: self, index })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u32, MappedItem<'_>)> {
+        (0..self.count).map(move |index| (self.id_at(index), MappedItem { catalog: self, index }))
+    }
+
+    // Copies the catalog out, e.g. to dump it
+    pub fn to_list(&self) -> ItemList {
+        self.iter().map(|(id, item)| (id, item.to_record())).collect()
+    }
+}
+
+// One item's entry, borrowed from the mapping
+#[derive(Clone, Copy)]
+pub struct MappedItem<'a> {
+    catalog: &'a MappedCatalog,
+    index: usize,
+}
+
+impl fmt::Debug for MappedItem<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("MappedItem").field(&self.name()).finish()
+    }
+}
+
+impl<'a> MappedItem<'a> {
+    fn entry(&self) -> usize {
+        HEADER_SIZE + self.catalog.count * 4 + self.index * ENTRY_SIZE
+    }
+
+    // `validate` checked every reference when the file was opened, but the mapping is shared with
+    // the file, so the bytes are checked again rather than trusted. A string that no longer reads
+    // as UTF-8 comes back absent.
+    fn string(&self, field: usize) -> Option<&'a str> {
+        let bytes = self.catalog.bytes();
+        let entry = self.entry() + field * 8;
+        let offset = read_u32(bytes, entry);
+        if offset == NONE {
+            return None;
+        }
+        let start = self.catalog.strings_start + offset as usize;
+        let end = start + read_u32(bytes, entry + 4) as usize;
+        std::str::from_utf8(bytes.get(start..end)?).ok()
+    }
+
+    pub fn name(&self) -> &'a str {
+        self.string(0).unwrap_or_default()
+    }
+
+    pub fn category(&self) -> Option<&'a str> {
+        self.string(1)
+    }
+
+    pub fn image_url(&self) -> Option<&'a str> {
+        self.string(2)
+    }
+
+    pub fn tags(&self) -> impl Iterator<Item = &'a str> {
+        self.string(3).unwrap_or_default().split(TAG_SEPARATOR).filter(|tag| !tag.is_empty())
+    }
+
+    pub fn min_stock(&self) -> Option<u32> {
+        Some(read_u32(self.catalog.bytes(), self.entry() + 32)).filter(|&stock| stock != u32::MAX)
+    }
+
+    pub fn max_price(&self) -> Option<u64> {
+        Some(read_u64(self.catalog.bytes(), self.entry() + 40)).filter(|&price| price != u64::MAX)
+    }
+
+    pub fn to_record(&self) -> ItemRecord {
+        ItemRecord {
+            name: self.name().to_string(),
+            category: self.category().map(str::to_string),
+            tags: self.tags().map(str::to_string).collect(),
+            min_stock: self.min_stock(),
+            max_price: self.max_price(),
+            image_url: self.image_url().map(str::to_string),
+        }
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+// Empty tags and thresholds of all ones can't be told apart from absent ones, so they read back
+// as absent
+pub fn encode_mapped(list: &ItemList, saved_at: u64) -> Result<Vec<u8>, CacheError> {
+    let too_large = || CacheError::Corrupt("catalog is too large for the mapped format".to_string());
+    let count = u32::try_from(list.len()).map_err(|_| too_large())?;
+
+    let mut entries = Vec::with_capacity(list.len() * ENTRY_SIZE);
+    let mut strings = String::new();
+    let mut push = |entries: &mut Vec<u8>, value: Option<&str>| -> Result<(), CacheError> {
+        let (offset, len) = match value {
+            Some(value) => {
+                let offset = u32::try_from(strings.len()).map_err(|_| too_large())?;
+                strings.push_str(value);
+                (offset, value.len() as u32)
+            }
+            None => (NONE, 0),
+        };
+        entries.extend_from_slice(&offset.to_le_bytes());
+        entries.extend_from_slice(&len.to_le_bytes());
+        Ok(())
+    };
+
+    let mut ids = Vec::with_capacity(list.len() * 4);
+    for (id, item) in list.iter() {
+        ids.extend_from_slice(&id.to_le_bytes());
+        let tags: Vec<&str> = item.tags.iter().map(String::as_str).filter(|tag| !tag.is_empty()).collect();
+        push(&mut entries, Some(&item.name))?;
+        push(&mut entries, item.category.as_deref())?;
+        push(&mut entries, item.image_url.as_deref())?;
+        push(&mut entries, Some(&tags.join(&TAG_SEPARATOR.to_string())))?;
+        entries.extend_from_slice(&item.min_stock.unwrap_or(u32::MAX).to_le_bytes());
+        entries.extend_from_slice(&[0; 4]);
+        entries.extend_from_slice(&item.max_price.unwrap_or(u64::MAX).to_le_bytes());
+    }
+    let strings_len = u32::try_from(strings.len()).map_err(|_| too_large())?;
+
+    let mut body = ids;
+    body.extend_from_slice(&entries);
+    body.extend_from_slice(strings.as_bytes());
+
+    let mut file = Vec::with_capacity(HEADER_SIZE + body.len());
+    file.extend_from_slice(&MAPPED_MAGIC);
+    file.extend_from_slice(&MAPPED_VERSION.to_le_bytes());
+    file.extend_from_slice(&saved_at.to_le_bytes());
+    file.extend_from_slice(&count.to_le_bytes());
+    file.extend_from_slice(&strings_len.to_le_bytes());
+    file.extend_from_slice(&crc32fast::hash(&body).to_le_bytes());
+    file.extend_from_slice(&[0; 4]);
+    file.extend_from_slice(&body);
+    Ok(file)
+}
+
+// Writes then renames, so catalogs already mapped from the old file keep reading it
+pub fn write_mapped(list: &ItemList, filename: &str, saved_at: u64) -> Result<(), CacheError> {
+    let bytes = encode_mapped(list, saved_at)?;
+    let tmp = format!("{}.tmp", filename);
+    fs::write(&tmp, bytes)?;
+    fs::rename(&tmp, filename)?;
+    Ok(())
+}
+
+// Whether a catalog file is in the mapped format, judged by its magic
+pub fn is_mapped(path: &str) -> io::Result<bool> {
+    let mut magic = [0u8; 4];
+    match File::open(path)?.read_exact(&mut magic) {
+        Ok(()) => Ok(magic == MAPPED_MAGIC),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::cache::CacheManager;
+
+    fn temp_path(test: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("h278_mapped_{}_{}.map", test, std::process::id()))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn sample() -> ItemList {
+        let mut list = ItemList::default();
+        list.insert(20067, ItemRecord {
+            name: "Sword".to_string(),
+            category: Some("weapon".to_string()),
+            tags: vec!["limited".to_string(), "bloody".to_string()],
+            min_stock: Some(2),
+            max_price: Some(500),
+            image_url: Some("https://example.com/20067.png".to_string()),
+        });
+        list.insert(5, ItemRecord::named("Bow"));
+        list.insert(20069, ItemRecord { category: Some(String::new()), ..ItemRecord::named("Shield") });
+        list
+    }
+
+    #[test]
+    fn mapped_catalogs_read_back_every_field() {
+        let path = temp_path("round_trip");
+        write_mapped(&sample(), &path, 1_700_000_000).unwrap();
+        assert!(is_mapped(&path).unwrap());
+
+        let catalog = MappedCatalog::open(&path).unwrap();
+        catalog.verify().unwrap();
+        assert_eq!(catalog.saved_at(), 1_700_000_000);
+        assert_eq!(catalog.iter().map(|(id, _)| id).collect::<Vec<_>>(), vec![5, 20067, 20069]);
+
+        let sword = catalog.get(20067).unwrap();
+        assert_eq!(sword.name(), "Sword");
+        assert_eq!(sword.tags().collect::<Vec<_>>(), vec!["limited", "bloody"]);
+        assert_eq!(catalog.get(20069).unwrap().category(), Some(""));
+        assert!(catalog.get(6).is_none() && catalog.get(0).is_none() && catalog.get(u32::MAX).is_none());
+        assert_eq!(catalog.to_list(), sample());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn damaged_files_are_refused() {
+        let path = temp_path("damaged");
+        let bytes = encode_mapped(&sample(), 0).unwrap();
+
+        fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+        assert!(matches!(MappedCatalog::open(&path), Err(CacheError::Corrupt(_))));
+
+        let mut future = bytes.clone();
+        future[4..8].copy_from_slice(&9u32.to_le_bytes());
+        fs::write(&path, &future).unwrap();
+        assert!(matches!(MappedCatalog::open(&path), Err(CacheError::UnsupportedVersion(9))));
+
+        // A string reference pointing past the table
+        let mut out_of_bounds = bytes.clone();
+        let name_len = HEADER_SIZE + 3 * 4 + 4;
+        out_of_bounds[name_len..name_len + 4].copy_from_slice(&1000u32.to_le_bytes());
+        fs::write(&path, &out_of_bounds).unwrap();
+        assert!(matches!(MappedCatalog::open(&path), Err(CacheError::Corrupt(_))));
+
+        // A flipped byte in a name still opens, but fails verification
+        let mut flipped = bytes.clone();
+        let last = flipped.len() - 1;
+        flipped[last] ^= 0x01;
+        fs::write(&path, &flipped).unwrap();
+        assert!(matches!(MappedCatalog::open(&path).unwrap().verify(), Err(CacheError::Corrupt(_))));
+        let loaded = CacheManager::new(u64::MAX).load_mapped(&path);
+        assert!(matches!(loaded, Err(CacheError::Corrupt(_))), "{:?}", loaded);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
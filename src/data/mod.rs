@@ -1 +1,7 @@
-pub mod cache;
\ No newline at end of file
+pub mod cache;
+pub mod catalog;
+pub mod format;
+pub mod history;
+pub mod import;
+pub mod mapped;
+pub mod reload;
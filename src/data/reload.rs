@@ -0,0 +1,298 @@
+use arc_swap::ArcSwap;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+use std::{fs, io};
+use crate::data::cache::{CacheError, CacheManager};
+use crate::data::catalog::{load_list, Catalog, CatalogSource};
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ReloadConfig {
+    pub enabled: bool,
+    // How often the polling watcher checks file times, and how often either watcher checks
+    // whether it should stop
+    pub poll_interval_ms: u64,
+    // Poll even where inotify is available, e.g. for network filesystems that don't report changes
+    pub force_polling: bool,
+}
+
+impl Default for ReloadConfig {
+    fn default() -> Self {
+        Self { enabled: true, poll_interval_ms: 2000, force_polling: false }
+    }
+}
+
+// Reloads catalog lists whose files change and swaps the new catalog in whole. Readers `load()`
+// the current catalog per frame and never see a half-updated one; a file that fails to load
+// leaves the previous version in place.
+pub struct CatalogReloader {
+    catalog: Arc<ArcSwap<Catalog>>,
+    sources: Vec<CatalogSource>,
+    cache_manager: CacheManager,
+}
+
+impl CatalogReloader {
+    pub fn new(catalog: Arc<ArcSwap<Catalog>>, sources: Vec<CatalogSource>, cache_manager: CacheManager) -> Self {
+        Self { catalog, sources, cache_manager }
+    }
+
+    // Reloads the named list from its file
+    pub fn reload(&self, name: &str) -> Result<(), CacheError> {
+        let Some(source) = self.sources.iter().find(|source| source.name == name) else {
+            return Ok(());
+        };
+        let list = load_list(&self.cache_manager, &source.path)?;
+
+        // Only this thread swaps, so nothing can be lost between load and store
+        let mut catalog = Catalog::clone(&self.catalog.load());
+        catalog.insert_index(&source.name, list);
+        self.catalog.store(Arc::new(catalog));
+        Ok(())
+    }
+
+    fn reload_logged(&self, name: &str) {
+        match self.reload(name) {
+            Ok(()) => {
+                let catalog = self.catalog.load();
+                let items = catalog.get(name).map_or(0, |list| list.len());
+                println!("Reloaded catalog '{}' with {} items", name, items);
+            }
+            Err(e) => eprintln!("Keeping previous catalog '{}', reload failed: {}", name, e),
+        }
+    }
+
+    // Watches the source files on a background thread until the handle is dropped
+    pub fn spawn(self, config: &ReloadConfig) -> io::Result<ReloadHandle> {
+        let interval = Duration::from_millis(config.poll_interval_ms.max(10));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        #[cfg(target_os = "linux")]
+        if !config.force_polling {
+            match inotify::Watcher::new(&self.sources) {
+                Ok(watcher) => {
+                    let thread = thread::Builder::new().name("catalog-reload".to_string()).spawn({
+                        let stop = Arc::clone(&stop);
+                        move || watcher.run(&self, interval, &stop)
+                    })?;
+                    return Ok(ReloadHandle { stop, thread: Some(thread) });
+                }
+                Err(e) => eprintln!("inotify unavailable ({}), polling catalogs instead", e),
+            }
+        }
+
+        let thread = thread::Builder::new().name("catalog-reload".to_string()).spawn({
+            let stop = Arc::clone(&stop);
+            move || self.poll(interval, &stop)
+        })?;
+        Ok(ReloadHandle { stop, thread: Some(thread) })
+    }
+
+    fn poll(&self, interval: Duration, stop: &AtomicBool) {
+        let stamp = |path: &str| fs::metadata(path).ok().map(|m| (m.modified().ok(), m.len()));
+        let mut stamps: HashMap<&str, Option<(Option<SystemTime>, u64)>> = self.sources.iter()
+            .map(|source| (source.path.as_str(), stamp(&source.path)))
+            .collect();
+
+        while !stop.load(Ordering::Relaxed) {
+            thread::sleep(interval);
+            for source in &self.sources {
+                let current = stamp(&source.path);
+                if current.is_some() && stamps.insert(&source.path, current) != Some(current) {
+                    self.reload_logged(&source.name);
+                }
+            }
+        }
+    }
+}
+
+pub struct ReloadHandle {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for ReloadHandle {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// The directory to watch and the file name to look for in it. Directories rather than files are
+// watched so a catalog replaced by rename is still seen.
+fn split_path(path: &str) -> (PathBuf, String) {
+    let path = Path::new(path);
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let file = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    (dir, file)
+}
+
+#[cfg(target_os = "linux")]
+mod inotify {
+    use super::*;
+    use std::ffi::CString;
+    use std::fs::File;
+    use std::io::Read;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+
+    const EVENT_HEADER_SIZE: usize = 16;
+
+    pub struct Watcher {
+        fd: File,
+        // Watch descriptor, file name, list name
+        targets: Vec<(i32, String, String)>,
+    }
+
+    impl Watcher {
+        pub fn new(sources: &[CatalogSource]) -> io::Result<Self> {
+            let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC | libc::IN_NONBLOCK) };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let fd = unsafe { File::from_raw_fd(fd) };
+
+            let mut targets = Vec::new();
+            for source in sources {
+                let (dir, file) = split_path(&source.path);
+                let dir = CString::new(dir.as_os_str().as_bytes())
+                    .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "path contains a NUL byte"))?;
+                // Adding the same directory twice hands back the same descriptor
+                let wd = unsafe {
+                    libc::inotify_add_watch(fd.as_raw_fd(), dir.as_ptr(), libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO)
+                };
+                if wd < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                targets.push((wd, file, source.name.clone()));
+            }
+
+            Ok(Watcher { fd, targets })
+        }
+
+        pub fn run(mut self, reloader: &CatalogReloader, interval: Duration, stop: &AtomicBool) {
+            let mut buffer = vec![0u8; 64 * 1024];
+            while !stop.load(Ordering::Relaxed) {
+                let mut pollfd = libc::pollfd { fd: self.fd.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+                if unsafe { libc::poll(&mut pollfd, 1, interval.as_millis() as libc::c_int) } <= 0 {
+                    continue;
+                }
+
+                let len = match self.fd.read(&mut buffer) {
+                    Ok(len) => len,
+                    Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted) => continue,
+                    Err(e) => {
+                        eprintln!("Stopping catalog reload, inotify failed: {}", e);
+                        return;
+                    }
+                };
+
+                // A burst of writes to one file only needs one reload
+                let mut changed: Vec<&str> = Vec::new();
+                for (wd, name) in parse_events(&buffer[..len]) {
+                    for (target_wd, file, list) in &self.targets {
+                        if *target_wd == wd && *file == name && !changed.contains(&list.as_str()) {
+                            changed.push(list);
+                        }
+                    }
+                }
+                for list in changed {
+                    reloader.reload_logged(list);
+                }
+            }
+        }
+    }
+
+    // (watch descriptor, file name) for each `inotify_event` in the buffer
+    fn parse_events(mut bytes: &[u8]) -> Vec<(i32, String)> {
+        let mut events = Vec::new();
+        while bytes.len() >= EVENT_HEADER_SIZE {
+            let wd = i32::from_ne_bytes(bytes[0..4].try_into().unwrap());
+            let name_len = u32::from_ne_bytes(bytes[12..16].try_into().unwrap()) as usize;
+            let Some(name) = bytes.get(EVENT_HEADER_SIZE..EVENT_HEADER_SIZE + name_len) else {
+                break;
+            };
+            // The name is NUL padded
+            let name = name.split(|&b| b == 0).next().unwrap_or_default();
+            events.push((wd, String::from_utf8_lossy(name).into_owned()));
+            bytes = &bytes[EVENT_HEADER_SIZE + name_len..];
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::format::{ItemList, ItemRecord};
+    use std::time::Instant;
+
+    fn list(name: &str) -> ItemList {
+        ItemList::from_iter([(1, ItemRecord::named(name))])
+    }
+
+    fn setup(test: &str) -> (PathBuf, Arc<ArcSwap<Catalog>>, CatalogReloader) {
+        let dir = std::env::temp_dir().join(format!("h278_reload_{}_{}", test, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("all.bin");
+        CacheManager::new(3600).save_cache(&list("Old"), path.to_str().unwrap()).unwrap();
+
+        let sources = vec![CatalogSource { name: "all".to_string(), path: path.to_string_lossy().into_owned() }];
+        let catalog = Catalog::load(&CacheManager::new(3600), &sources).unwrap();
+        let catalog = Arc::new(ArcSwap::from_pointee(catalog));
+        let reloader = CatalogReloader::new(Arc::clone(&catalog), sources, CacheManager::new(3600));
+        (dir, catalog, reloader)
+    }
+
+    fn wait_for_name(catalog: &ArcSwap<Catalog>, name: &str) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while catalog.load().lookup(1).name() != Some(name) {
+            assert!(Instant::now() < deadline, "catalog never reloaded to {}", name);
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn failed_reload_keeps_the_previous_catalog() {
+        let (dir, catalog, reloader) = setup("corrupt");
+        let before = catalog.load_full();
+
+        fs::write(dir.join("all.bin"), b"not bincode").unwrap();
+        assert!(reloader.reload("all").is_err());
+        assert!(Arc::ptr_eq(&before, &catalog.load_full()));
+
+        CacheManager::new(3600).save_cache(&list("New"), dir.join("all.bin").to_str().unwrap()).unwrap();
+        reloader.reload("all").unwrap();
+        assert_eq!(catalog.load().lookup(1).name(), Some("New"));
+        // Readers holding the old catalog keep a consistent view
+        assert_eq!(before.lookup(1).name(), Some("Old"));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn watchers_pick_up_rewritten_files() {
+        for force_polling in [false, true] {
+            let (dir, catalog, reloader) = setup(if force_polling { "poll" } else { "notify" });
+            let config = ReloadConfig { enabled: true, poll_interval_ms: 20, force_polling };
+            let handle = reloader.spawn(&config).unwrap();
+
+            // Give the polling watcher a tick to record the original file first
+            thread::sleep(Duration::from_millis(50));
+            CacheManager::new(3600).save_cache(&list("New"), dir.join("all.bin").to_str().unwrap()).unwrap();
+            wait_for_name(&catalog, "New");
+
+            drop(handle);
+            fs::remove_dir_all(dir).unwrap();
+        }
+    }
+}
@@ -4,6 +4,12 @@ pub struct BitBuffer {
     pub bits_in_buffer: u8,
 }
 
+impl Default for BitBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl BitBuffer {
     pub fn new() -> Self {
         BitBuffer {
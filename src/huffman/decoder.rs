@@ -1,16 +1,57 @@
 use std::cmp::Reverse;
 use std::collections::{BinaryHeap, HashMap};
+use std::fmt;
 use crate::huffman::bit_buffer::BitBuffer;
 use crate::huffman::hybrid_lookup_table::HybridLookupTable;
 use crate::huffman::node::Node;
 use crate::MAX_SHORT_BITS;
 
+// Frame layout offsets: symbol table start, bytes per symbol, and the bit/byte counts after it
+const SYMBOLS_START: usize = 12;
+const SYMBOL_SIZE: usize = 8;
+const COUNTS_SIZE: usize = 12;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    // The header describes more bytes than the frame has
+    Truncated { needed: usize, len: usize },
+    // A tree needs two symbols to have any codes
+    TooFewSymbols(usize),
+    // Each symbol may appear once in the table
+    DuplicateSymbol(u8),
+    // More bits than the packed bytes hold
+    BitsOverrun { bits: usize, bytes: usize },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Truncated { needed, len } => {
+                write!(f, "Frame of {} bytes is truncated, header needs {}", len, needed)
+            }
+            DecodeError::TooFewSymbols(count) => write!(f, "Frame has {} symbols, at least 2 are needed", count),
+            DecodeError::DuplicateSymbol(byte) => write!(f, "Frame lists symbol {:#04x} more than once", byte),
+            DecodeError::BitsOverrun { bits, bytes } => {
+                write!(f, "Frame claims {} bits in {} packed bytes", bits, bytes)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
 pub struct OptimizedHuffmanDecoder {
     tree: Option<Node>,
     freqs: HashMap<char, usize>,
     lookup_table: HybridLookupTable,
 }
 
+impl Default for OptimizedHuffmanDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl OptimizedHuffmanDecoder {
     pub fn new() -> Self {
         OptimizedHuffmanDecoder {
@@ -48,6 +89,8 @@ impl OptimizedHuffmanDecoder {
     }
 
     pub fn build_codes(&mut self) {
+        // Entries left by the previous frame's tree would shadow codes that don't fit the table
+        self.lookup_table.clear();
         if let Some(ref root) = self.tree.clone() {
             self.build_codes_recursive(root, String::new());
         }
@@ -86,6 +129,44 @@ impl OptimizedHuffmanDecoder {
         pos
     }
 
+    // Checks the header against the frame's length before decoding, so a malformed frame is an
+    // error rather than a panic or an endless loop
+    pub fn try_decode_to_bytes(&mut self, data: &[u8]) -> Result<Vec<u8>, DecodeError> {
+        let read_u32 = |pos: usize| u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap()) as usize;
+        let check = |needed: usize| {
+            if needed <= data.len() {
+                Ok(())
+            } else {
+                Err(DecodeError::Truncated { needed, len: data.len() })
+            }
+        };
+
+        check(SYMBOLS_START)?;
+        let symbols = read_u32(8);
+        if symbols < 2 {
+            return Err(DecodeError::TooFewSymbols(symbols));
+        }
+        let counts = SYMBOLS_START + symbols * SYMBOL_SIZE;
+        check(counts + COUNTS_SIZE)?;
+
+        // Repeats would collapse into one leaf, leaving a tree with no codes to consume bits
+        let mut seen = [false; 256];
+        for index in 0..symbols {
+            let byte = data[SYMBOLS_START + index * SYMBOL_SIZE + 4];
+            if std::mem::replace(&mut seen[byte as usize], true) {
+                return Err(DecodeError::DuplicateSymbol(byte));
+            }
+        }
+
+        let (bits, bytes) = (read_u32(counts), read_u32(counts + 4));
+        if bits > bytes.saturating_mul(8) {
+            return Err(DecodeError::BitsOverrun { bits, bytes });
+        }
+        check(counts + COUNTS_SIZE + bytes)?;
+
+        Ok(self.decode_to_bytes(data))
+    }
+
     pub fn decode_to_bytes(&mut self, data: &[u8]) -> Vec<u8> {
         let pos = self.parse_header_fast(data);
         self.build_efficient_tree();
@@ -0,0 +1,211 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fmt;
+use crate::huffman::node::Node;
+
+// Produces the frames `OptimizedHuffmanDecoder::decode_to_bytes` reads, little endian:
+//
+//   0   original length (u64)      skipped by the decoder
+//   8   symbol count (u32)
+//   12  per symbol: frequency (u32), byte, 3 bytes padding
+//   ..  packed bits (u32), packed bytes (u32), 4 bytes the decoder skips
+//   ..  codes, most significant bit first
+//
+// The decoder rebuilds the tree from the frequencies, so they are chosen for it rather than
+// copied from the input:
+// - Ties would be broken by `HashMap` order, which differs between processes. Each symbol gets
+//   its own bit below the real count, so every subtree has a distinct total.
+// - It stops once fewer than 8 bits are left, so the input is padded with trailing '|' (which the
+//   stock parser ignores) until the last code ends in the final byte.
+const TIE_BITS: u32 = 16;
+pub const MAX_SYMBOLS: usize = TIE_BITS as usize;
+const FILLER: u8 = b'|';
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EncodeError {
+    // Only MAX_SYMBOLS symbols get a tie-breaking bit, counting the filler
+    TooManySymbols(usize),
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncodeError::TooManySymbols(count) => {
+                write!(f, "Input has {} distinct bytes with the filler, at most {} can be encoded", count, MAX_SYMBOLS)
+            }
+        }
+    }
+}
+
+impl std::error::Error for EncodeError {}
+
+pub fn encode(input: &[u8]) -> Result<Vec<u8>, EncodeError> {
+    let mut counts = [0u32; 256];
+    for &byte in input {
+        counts[byte as usize] += 1;
+    }
+    // The filler and a second symbol are always present, so there are real codes to write
+    counts[FILLER as usize] += 1;
+    if counts.iter().filter(|&&count| count > 0).count() < 2 {
+        counts[b'0' as usize] += 1;
+    }
+    let symbols: Vec<u8> = (0..=255u8).filter(|&byte| counts[byte as usize] > 0).collect();
+    if symbols.len() > MAX_SYMBOLS {
+        return Err(EncodeError::TooManySymbols(symbols.len()));
+    }
+
+    let freqs = weighted(&symbols, |byte| counts[byte as usize]);
+    let codes = code_table(&freqs);
+    let code_for = |byte: u8| &codes.iter().find(|(symbol, _)| *symbol == byte).unwrap().1;
+
+    let mut bits = BitWriter::default();
+    for &byte in input {
+        bits.push(code_for(byte));
+    }
+    let filler = code_for(FILLER);
+    loop {
+        let last = bits.last_code_len;
+        let padding = (8 - bits.len % 8) % 8;
+        if last > 0 && last + padding >= 8 {
+            break;
+        }
+        bits.push(filler);
+    }
+
+    let mut frame = Vec::with_capacity(24 + freqs.len() * 8 + bits.bytes.len());
+    frame.extend_from_slice(&(input.len() as u64).to_le_bytes());
+    frame.extend_from_slice(&(freqs.len() as u32).to_le_bytes());
+    for &(byte, freq) in &freqs {
+        frame.extend_from_slice(&freq.to_le_bytes());
+        frame.extend_from_slice(&[byte, 0, 0, 0]);
+    }
+    frame.extend_from_slice(&(bits.len as u32).to_le_bytes());
+    frame.extend_from_slice(&(bits.bytes.len() as u32).to_le_bytes());
+    frame.extend_from_slice(&[0; 4]);
+    frame.extend_from_slice(&bits.bytes);
+    Ok(frame)
+}
+
+// Counts scaled into the high bits, symbol index in the low ones
+fn weighted(symbols: &[u8], count: impl Fn(u8) -> u32) -> Vec<(u8, u32)> {
+    let max = symbols.iter().map(|&byte| count(byte)).max().unwrap_or(1);
+    let limit = (u32::MAX >> TIE_BITS) / symbols.len() as u32;
+    symbols.iter()
+        .enumerate()
+        .map(|(index, &byte)| {
+            // At least 1, so rare symbols keep a place in the tree
+            let scaled = (count(byte) as u64 * limit as u64 / max as u64).max(1) as u32;
+            (byte, (scaled << TIE_BITS) | (1 << index))
+        })
+        .collect()
+}
+
+// The codes the decoder derives from these frequencies: the same heap, merging the two lowest
+// totals with the first as the 0 branch
+fn code_table(freqs: &[(u8, u32)]) -> Vec<(u8, Vec<bool>)> {
+    let mut heap = BinaryHeap::new();
+    for &(byte, freq) in freqs {
+        heap.push(Reverse((freq as usize, heap.len(), Node::leaf(byte as char, freq as usize))));
+    }
+    while heap.len() > 1 {
+        let Reverse((freq1, _, node1)) = heap.pop().unwrap();
+        let Reverse((freq2, _, node2)) = heap.pop().unwrap();
+        let combined = freq1 + freq2;
+        heap.push(Reverse((combined, heap.len(), Node::internal(combined, node1, node2))));
+    }
+
+    let mut codes = Vec::new();
+    if let Some(Reverse((_, _, root))) = heap.pop() {
+        collect_codes(&root, &mut Vec::new(), &mut codes);
+    }
+    codes
+}
+
+fn collect_codes(node: &Node, code: &mut Vec<bool>, codes: &mut Vec<(u8, Vec<bool>)>) {
+    match (&node.left, &node.right) {
+        (Some(left), Some(right)) => {
+            code.push(false);
+            collect_codes(left, code, codes);
+            code.pop();
+            code.push(true);
+            collect_codes(right, code, codes);
+            code.pop();
+        }
+        _ => codes.push((node.char.unwrap() as u8, code.clone())),
+    }
+}
+
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    len: usize,
+    last_code_len: usize,
+}
+
+impl BitWriter {
+    fn push(&mut self, code: &[bool]) {
+        for &bit in code {
+            if self.len.is_multiple_of(8) {
+                self.bytes.push(0);
+            }
+            if bit {
+                *self.bytes.last_mut().unwrap() |= 0x80 >> (self.len % 8);
+            }
+            self.len += 1;
+        }
+        self.last_code_len = code.len();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::huffman::decoder::OptimizedHuffmanDecoder;
+
+    #[test]
+    fn decoder_reads_back_what_was_encoded() {
+        let skewed = "1".repeat(5000) + "2-|";
+        let inputs = [
+            "20067-0-104-1100000000|20069-1-47-1100000000|21021-0-447-1630000000|",
+            "1-1-1-1|",
+            skewed.as_str(),
+            "",
+            "20067-3-104-1100000000|",
+        ];
+
+        // One decoder for all of them, as the server uses
+        let mut decoder = OptimizedHuffmanDecoder::new();
+        for input in inputs {
+            let decoded = decoder.decode_to_bytes(&encode(input.as_bytes()).unwrap());
+            let decoded = String::from_utf8(decoded).unwrap();
+            assert_eq!(decoded.trim_end_matches('|'), input.trim_end_matches('|'), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn codes_longer_than_the_decoder_table_round_trip() {
+        // Fibonacci counts give the deepest possible tree
+        let mut fib = (1, 1);
+        let mut input = String::new();
+        for symbol in "0123456789-|".chars() {
+            input.extend(std::iter::repeat_n(symbol, fib.0));
+            fib = (fib.1, fib.0 + fib.1);
+        }
+        let counts = |byte: u8| input.bytes().filter(|&b| b == byte).count() as u32;
+        let deep = code_table(&weighted(b"-0123456789|", counts));
+        assert!(deep.iter().any(|(_, code)| code.len() > crate::MAX_SHORT_BITS));
+
+        // After a frame whose codes all fit the table, so nothing of it may linger
+        let mut decoder = OptimizedHuffmanDecoder::new();
+        decoder.decode_to_bytes(&encode(b"20067-3-104-1100000000|").unwrap());
+        let decoded = decoder.decode_to_bytes(&encode(input.as_bytes()).unwrap());
+        assert_eq!(String::from_utf8(decoded).unwrap().trim_end_matches('|'), input.trim_end_matches('|'));
+    }
+
+    #[test]
+    fn too_many_distinct_bytes_is_an_error() {
+        let input: Vec<u8> = (b'a'..=b'p').collect();
+        assert_eq!(encode(&input), Err(EncodeError::TooManySymbols(17)));
+        assert!(encode(&input[..15]).is_ok());
+    }
+}
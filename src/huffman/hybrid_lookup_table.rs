@@ -16,6 +16,12 @@ impl HybridLookupTable {
         }
     }
 
+    // Forgets every code, so a new tree starts from an empty table
+    pub fn clear(&mut self) {
+        self.short_table.clear();
+        self.long_codes.clear();
+    }
+
     pub fn add_code(&mut self, code: &str, character: char) {
         let code_int = u64::from_str_radix(code, 2).unwrap();
         let code_len = code.len() as u8;
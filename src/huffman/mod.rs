@@ -2,3 +2,4 @@ pub mod hybrid_lookup_table;
 pub mod bit_buffer;
 pub mod node;
 pub mod decoder;
+pub mod encoder;
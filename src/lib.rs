@@ -0,0 +1,7 @@
+pub mod huffman;
+pub mod service;
+pub mod memory;
+pub mod data;
+pub mod config;
+
+pub const MAX_SHORT_BITS: usize = 8;
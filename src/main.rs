@@ -1,16 +1,20 @@
-mod huffman;
-mod service;
-mod memory;
-mod data;
-use crate::huffman::decoder::OptimizedHuffmanDecoder;
-use crate::service::reader_service::parse_stock_bytes;
-use crate::service::channel::{ DiscordChannels};
-use crate::memory::server::SharedMemoryServer;
-
-use std::time::{Instant};
+use huffman_decoder::service::channel::{ DiscordChannels};
+use huffman_decoder::service::dedup::AlertDeduplicator;
+use huffman_decoder::service::pipeline::{DiscordOutput, Pipeline};
+use huffman_decoder::service::template::AlertRenderer;
+use huffman_decoder::service::rules::RuleEngine;
+use huffman_decoder::config::AppConfig;
+use huffman_decoder::memory::capture::{ReplayOptions, ReplaySource};
+use huffman_decoder::memory::transport::AnyFrameSource;
+
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use arc_swap::ArcSwap;
 use serenity::prelude::*;
 use dotenv::dotenv;
-use crate::data::cache::{CacheManager, H9123};
+use huffman_decoder::data::catalog::Catalog;
+use huffman_decoder::data::history::HistoryStore;
+use huffman_decoder::data::reload::CatalogReloader;
 
 //1309907112964653117
 //1309907121663508601
@@ -18,91 +22,190 @@ use crate::data::cache::{CacheManager, H9123};
 //1309907135949180988
 
 
-
-const MAX_SHORT_BITS: usize = 8;
-const SHARED_MEMORY_SIZE: usize = 1024 * 1024; // 1MB
-
-
 #[tokio::main]
 async fn main() {
     dotenv().ok();
 
-    let cache_manager = CacheManager::new(3600);
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let replay = match ReplayOptions::from_args(&args) {
+        Ok(replay) => replay,
+        Err(err) => {
+            eprintln!("{}\nUsage: HuffmanDecoder [replay <capture> [--speed <factor>|--speed max] [--live-sinks]]", err);
+            return;
+        }
+    };
 
-    // Load cached data
-    let listone = match cache_manager.load_cache("src/data/db/all.bin") {
-        Ok(list) => list,
+    let config_path = std::env::var("H278_CONFIG").unwrap_or_else(|_| "config.json".to_string());
+    let config = match AppConfig::load(&config_path) {
+        Ok(config) => config,
         Err(err) => {
-            eprintln!("Failed to load cache: {}", err);
-            return;  // or handle the error in another way
+            eprintln!("Failed to load config {}: {}", config_path, err);
+            return;
+        }
+    };
+
+    let renderer = match AlertRenderer::new(&config.alerts) {
+        Ok(renderer) => renderer,
+        Err(err) => {
+            eprintln!("Invalid alert template: {}", err);
+            return;
+        }
+    };
+
+    let rules = match RuleEngine::new(config.rules.clone()) {
+        Ok(rules) => rules,
+        Err(err) => {
+            eprintln!("Invalid alert rules: {}", err);
+            return;
         }
     };
 
-    let listwo = match cache_manager.load_cache("src/data/db/bloody.bin") {
-        Ok(list) => list,
+    let cache_manager = config.cache.manager();
+
+    // Load cached data
+    let catalog = match Catalog::load(&cache_manager, &config.catalogs) {
+        Ok(catalog) => catalog,
         Err(err) => {
             eprintln!("Failed to load cache: {}", err);
             return;
         }
     };
 
-    // Create searcher
-    let searcher = H9123::new(listone, listwo);
+    for name in catalog.list_names() {
+        let list = catalog.get(name).unwrap();
+        if list.is_empty() {
+            eprintln!("Warning: catalog '{}' is empty", name);
+        } else {
+            let age = catalog.age(name).unwrap().as_secs();
+            println!("Loaded catalog '{}' with {} items ({}s old)", name, list.len(), age);
+        }
+    }
 
+    for (rule, list) in rules.referenced_lists() {
+        if !catalog.list_names().any(|name| name == list) {
+            eprintln!("Warning: rule '{}' refers to unknown list '{}'", rule, list);
+        }
+    }
+
+    // Frames read whichever catalog is current; the reloader swaps in changed files
+    let catalog = Arc::new(ArcSwap::from_pointee(catalog));
+    let _reloader = if config.catalog_reload.enabled {
+        let reloader = CatalogReloader::new(Arc::clone(&catalog), config.catalogs.clone(), config.cache.manager());
+        match reloader.spawn(&config.catalog_reload) {
+            Ok(handle) => Some(handle),
+            Err(err) => {
+                eprintln!("Failed to start catalog reload: {}", err);
+                None
+            }
+        }
+    } else {
+        None
+    };
 
     let secret ="";
     let client = Client::builder(secret, GatewayIntents::empty())
         .await
         .expect("Failed to create client");
 
-    let discord_channels = DiscordChannels::new(client.http.clone());
+    let discord_channels = DiscordChannels::with_config(client.http.clone(), config.alert_queue.clone());
 
+    let mut dedup_config = config.dedup.clone();
+    if let Some(secs) = std::env::var("ALERT_COOLDOWN_SECS").ok().and_then(|v| v.parse().ok()) {
+        dedup_config.global_cooldown = Duration::from_secs(secs);
+    }
+    // A replay starts from a clean slate and leaves the live state alone
+    if replay.is_some() {
+        dedup_config.state_path = None;
+    }
+    let dedup = match AlertDeduplicator::load(dedup_config) {
+        Ok(dedup) => dedup,
+        Err(err) => {
+            eprintln!("Failed to load dedup state: {}", err);
+            return;
+        }
+    };
 
-    let mut server = SharedMemoryServer::new("h278", SHARED_MEMORY_SIZE)
-        .expect("Failed to create shared memory server");
-    let mut decoder = OptimizedHuffmanDecoder::new();
+    let opened = match &replay {
+        Some(replay) => ReplaySource::open(&replay.path, replay.speed).map(AnyFrameSource::Replay),
+        None => AnyFrameSource::open(&config.transport).await,
+    };
+    let mut source = match opened {
+        Ok(source) => source,
+        Err(err) => {
+            match &replay {
+                Some(replay) => eprintln!("Failed to open capture {}: {}", replay.path, err),
+                None => eprintln!("Failed to open {:?}: {}", config.transport, err),
+            }
+            return;
+        }
+    };
+    // Stubbed sinks print what would have been sent to Discord
+    let stub_sinks = replay.as_ref().is_some_and(|replay| !replay.live_sinks);
+
+    let mut pipeline = Pipeline::new(
+        catalog,
+        rules,
+        renderer,
+        dedup,
+        DiscordOutput::new(discord_channels).with_stub(stub_sinks),
+    );
+    if replay.is_none() {
+        pipeline = pipeline.with_dedup_saving();
+    }
+
+    if config.capture.enabled && replay.is_none() {
+        let started_at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        match config.capture.create_writer(started_at) {
+            Ok((writer, path)) => {
+                println!("Capturing frames to {}", path.display());
+                pipeline = pipeline.with_capture(writer);
+            }
+            Err(err) => {
+                eprintln!("Failed to create frame capture {}: {}", config.capture.path, err);
+                return;
+            }
+        }
+    }
+
+    if config.history.enabled && replay.is_none() {
+        match HistoryStore::open(&config.history) {
+            Ok(store) => pipeline = pipeline.with_history(store),
+            Err(err) => {
+                eprintln!("Failed to open stock history in {}: {}", config.history.dir, err);
+                return;
+            }
+        }
+    }
 
     println!("Server started. Waiting for data...");
 
-    while server.wait_for_data() {
-        let start = Instant::now();
-        match server.process_data() {
-            Ok((client_id, shared_mem_data)) => {
-                let duration = start.elapsed();
-                let decoded = decoder.decode_to_bytes(&shared_mem_data);
-                let result = parse_stock_bytes(&decoded);
-
-                if !result.is_empty() {
-                    let search_ids: Vec<String> = result
-                        .iter()
-                        .map(|(id, _count)| id.to_string())
-                        .collect();
-
-                    let search_results = searcher.search_standard(&search_ids);
-                    println!("Time taken: {} ns", duration.as_nanos());
-
-                    // Filter for items only in first list (has first value but no second value)
-                    for (id, first_value, second_value) in &search_results {
-                        if second_value.is_none() && first_value.is_some() {
-                            if let Some(&(_, stock)) = result.iter().find(|(result_id, _)| result_id.to_string() == *id) {
-                                let message: String = format!(
-                                    "@everyone Client {}: Item: {} (ID: {}) - Stock: {}",
-                                    client_id,
-                                    first_value.as_ref().unwrap(),
-                                    id,
-                                    stock
-                                );
-
-                                // Clone http for each iteration
-                                discord_channels.send_message(client_id, message);
-                                println!("COMPLETE OUTFIT NOTIF TIME TAKEN: {} ns", duration.as_nanos());
-
-                            }
-                        }
-                    }
-                }
-            },
-            Err(e) => eprintln!("Error processing data: {}", e),
+    // Leaving the loop drops the source, which removes the shared memory segment
+    let shutdown = tokio::signal::ctrl_c();
+    tokio::pin!(shutdown);
+
+    // Waiting happens off the runtime's worker threads so the alert queues keep running
+    loop {
+        let processed = tokio::select! {
+            processed = pipeline.next_frame(&mut source) => processed,
+            _ = &mut shutdown => {
+                println!("Shutting down");
+                break;
+            }
+        };
+
+        match processed {
+            Ok(_) => {}
+            Err(e) if replay.is_some() && e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                println!("Replay finished");
+                break;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::InvalidData => {
+                eprintln!("Error processing data: {}", e);
+            }
+            Err(e) => {
+                eprintln!("Error waiting for data: {}", e);
+                break;
+            }
         }
     }
 }
@@ -0,0 +1,389 @@
+use serde::Deserialize;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::time::Instant;
+use crate::memory::transport::FrameSource;
+
+// A capture holds frames exactly as the server received them, so a replay goes through the same
+// decoding as the original run. After the magic and version, each frame is (little endian):
+//
+//   payload length (u32), CRC32 of the payload (u32), client id (u32), received at (u64 Unix
+//   millis), payload
+const CAPTURE_MAGIC: [u8; 4] = *b"H2CP";
+const CAPTURE_VERSION: u32 = 1;
+const FRAME_HEADER_SIZE: usize = 20;
+const MAX_CAPTURED_FRAME: usize = 16 * 1024 * 1024;
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct CaptureConfig {
+    pub enabled: bool,
+    // Each run writes its own file next to this one, named by its start time
+    pub path: String,
+    // Frames are buffered; this bounds how many a crash can lose
+    pub flush_interval_ms: u64,
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        Self { enabled: false, path: "capture.h2cp".to_string(), flush_interval_ms: 1000 }
+    }
+}
+
+impl CaptureConfig {
+    // `path` with the run's start time (Unix seconds) before the extension, e.g.
+    // capture-1700000000.h2cp, and a counter after it for later runs in the same second
+    pub fn run_path(&self, started_at: u64, attempt: u32) -> PathBuf {
+        let path = Path::new(&self.path);
+        let stem = path.file_stem().map(|stem| stem.to_string_lossy()).unwrap_or_default();
+        let mut name = format!("{}-{}", stem, started_at);
+        if attempt > 0 {
+            name += &format!("-{}", attempt);
+        }
+        if let Some(extension) = path.extension() {
+            name += &format!(".{}", extension.to_string_lossy());
+        }
+        path.with_file_name(name)
+    }
+
+    // Starts this run's capture at the first `run_path` not already taken
+    pub fn create_writer(&self, started_at: u64) -> io::Result<(CaptureWriter, PathBuf)> {
+        let mut attempt = 0;
+        loop {
+            let path = self.run_path(started_at, attempt);
+            match CaptureWriter::create(&path) {
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => attempt += 1,
+                created => {
+                    let interval = Duration::from_millis(self.flush_interval_ms);
+                    return created.map(|writer| (writer.with_flush_interval(interval), path));
+                }
+            }
+        }
+    }
+}
+
+pub struct CaptureWriter {
+    file: BufWriter<File>,
+    flush_interval: Duration,
+    last_flush: std::time::Instant,
+}
+
+impl CaptureWriter {
+    // Fails with `AlreadyExists` rather than replacing an earlier capture
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new().write(true).create_new(true).open(path)?;
+        let mut file = BufWriter::new(file);
+        file.write_all(&CAPTURE_MAGIC)?;
+        file.write_all(&CAPTURE_VERSION.to_le_bytes())?;
+        let flush_interval = Duration::from_millis(CaptureConfig::default().flush_interval_ms);
+        Ok(CaptureWriter { file, flush_interval, last_flush: std::time::Instant::now() })
+    }
+
+    pub fn with_flush_interval(mut self, flush_interval: Duration) -> Self {
+        self.flush_interval = flush_interval;
+        self
+    }
+
+    pub fn write(&mut self, client_id: u32, received_at: u64, payload: &[u8]) -> io::Result<()> {
+        let mut header = [0u8; FRAME_HEADER_SIZE];
+        header[0..4].copy_from_slice(&(payload.len() as u32).to_le_bytes());
+        header[4..8].copy_from_slice(&crc32fast::hash(payload).to_le_bytes());
+        header[8..12].copy_from_slice(&client_id.to_le_bytes());
+        header[12..20].copy_from_slice(&received_at.to_le_bytes());
+        self.file.write_all(&header)?;
+        self.file.write_all(payload)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.last_flush = std::time::Instant::now();
+        self.file.flush()
+    }
+
+    // Flushes once the interval has passed since the last flush
+    pub fn flush_if_due(&mut self) -> io::Result<()> {
+        if self.last_flush.elapsed() >= self.flush_interval {
+            self.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for CaptureWriter {
+    fn drop(&mut self) {
+        if let Err(e) = self.file.flush() {
+            eprintln!("Failed to flush frame capture: {}", e);
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CapturedFrame {
+    pub client_id: u32,
+    pub received_at: u64,
+    pub payload: Vec<u8>,
+}
+
+pub struct CaptureReader {
+    file: BufReader<File>,
+}
+
+impl CaptureReader {
+    pub fn open(path: &str) -> io::Result<Self> {
+        let mut file = BufReader::new(File::open(path)?);
+        let mut header = [0u8; 8];
+        file.read_exact(&mut header)?;
+        if header[0..4] != CAPTURE_MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} is not a frame capture", path)));
+        }
+        let version = u32::from_le_bytes(header[4..8].try_into().unwrap());
+        if version != CAPTURE_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported capture version {}", version),
+            ));
+        }
+        Ok(CaptureReader { file })
+    }
+
+    // `Ok(None)` at the end of the capture. A frame cut short by a crash also ends it.
+    pub fn next_frame(&mut self) -> io::Result<Option<CapturedFrame>> {
+        let mut header = [0u8; FRAME_HEADER_SIZE];
+        match self.file.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let len = u32::from_le_bytes(header[0..4].try_into().unwrap()) as usize;
+        let checksum = u32::from_le_bytes(header[4..8].try_into().unwrap());
+        if len > MAX_CAPTURED_FRAME {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("captured frame of {} bytes", len)));
+        }
+
+        let mut payload = vec![0u8; len];
+        match self.file.read_exact(&mut payload) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        if crc32fast::hash(&payload) != checksum {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "captured frame checksum does not match"));
+        }
+
+        Ok(Some(CapturedFrame {
+            client_id: u32::from_le_bytes(header[8..12].try_into().unwrap()),
+            received_at: u64::from_le_bytes(header[12..20].try_into().unwrap()),
+            payload,
+        }))
+    }
+}
+
+// Slower replays would wait hours between frames a second apart
+pub const MIN_REPLAY_SPEED: f64 = 1e-3;
+
+// Command line options for `replay <capture> [--speed <factor>|--speed max] [--live-sinks]`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplayOptions {
+    pub path: String,
+    // How many times faster than recorded; None replays without waiting
+    pub speed: Option<f64>,
+    // Alerts are printed rather than sent unless this is set
+    pub live_sinks: bool,
+}
+
+impl ReplayOptions {
+    // `Ok(None)` when the arguments don't ask for a replay
+    pub fn from_args(args: &[String]) -> Result<Option<Self>, String> {
+        let [mode, path, rest @ ..] = args else {
+            return match args.first().map(String::as_str) {
+                Some("replay") => Err("replay needs a capture file".to_string()),
+                _ => Ok(None),
+            };
+        };
+        if mode != "replay" {
+            return Ok(None);
+        }
+
+        let mut options = ReplayOptions { path: path.clone(), speed: Some(1.0), live_sinks: false };
+        let mut rest = rest.iter();
+        while let Some(arg) = rest.next() {
+            match arg.as_str() {
+                "--live-sinks" => options.live_sinks = true,
+                "--speed" => {
+                    options.speed = match rest.next().map(String::as_str) {
+                        Some("max") => None,
+                        Some(factor) => match factor.parse::<f64>() {
+                            Ok(factor) if factor.is_finite() && factor >= MIN_REPLAY_SPEED => Some(factor),
+                            _ => return Err(format!("invalid speed '{}'", factor)),
+                        },
+                        None => return Err("--speed needs a factor or 'max'".to_string()),
+                    }
+                }
+                other => return Err(format!("unknown replay option '{}'", other)),
+            }
+        }
+        Ok(Some(options))
+    }
+}
+
+// Serves a capture's frames with the gaps between them kept, scaled by the speed. The end of the
+// capture is reported as `UnexpectedEof`.
+pub struct ReplaySource {
+    reader: CaptureReader,
+    speed: Option<f64>,
+    // When the replay started, and the receive time of the first frame
+    origin: Option<(Instant, u64)>,
+    last_received_at: Option<u64>,
+}
+
+impl ReplaySource {
+    pub fn open(path: &str, speed: Option<f64>) -> io::Result<Self> {
+        Ok(ReplaySource { reader: CaptureReader::open(path)?, speed, origin: None, last_received_at: None })
+    }
+}
+
+impl FrameSource for ReplaySource {
+    // Captures are read with blocking calls; they come from local disk through a buffer
+    async fn recv_with<R>(&mut self, f: impl FnOnce(u32, &[u8]) -> R) -> io::Result<R> {
+        let Some(frame) = self.reader.next_frame()? else {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "end of capture"));
+        };
+
+        if let Some(speed) = self.speed {
+            let (started, first) = *self.origin.get_or_insert((Instant::now(), frame.received_at));
+            let offset = frame.received_at.saturating_sub(first) as f64 / speed;
+            let deadline = Duration::try_from_secs_f64(offset / 1000.0)
+                .ok()
+                .and_then(|offset| started.checked_add(offset))
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("replay speed {} is too slow", speed)))?;
+            tokio::time::sleep_until(deadline).await;
+        }
+
+        self.last_received_at = Some(frame.received_at);
+        Ok(f(frame.client_id, &frame.payload))
+    }
+
+    fn recorded_at(&self) -> Option<u64> {
+        self.last_received_at
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn capture(test: &str, frames: &[(u32, u64, &[u8])]) -> String {
+        let path = std::env::temp_dir()
+            .join(format!("h278_capture_{}_{}.h2cp", test, std::process::id()))
+            .to_string_lossy()
+            .into_owned();
+        let _ = std::fs::remove_file(&path);
+        let mut writer = CaptureWriter::create(&path).unwrap();
+        for &(client_id, received_at, payload) in frames {
+            writer.write(client_id, received_at, payload).unwrap();
+        }
+        path
+    }
+
+    #[tokio::test]
+    async fn replay_keeps_order_and_scaled_gaps() {
+        let path = capture("replay", &[(1, 10_000, b"first"), (2, 10_100, b""), (1, 10_300, b"third")]);
+        let mut source = ReplaySource::open(&path, Some(2.0)).unwrap();
+
+        let started = Instant::now();
+        let mut frames = Vec::new();
+        for _ in 0..3 {
+            frames.push(source.recv_with(|client_id, data| (client_id, data.to_vec())).await.unwrap());
+        }
+        // 300ms of capture at double speed
+        assert!(started.elapsed() >= Duration::from_millis(150));
+        assert_eq!(frames, vec![(1, b"first".to_vec()), (2, Vec::new()), (1, b"third".to_vec())]);
+        assert_eq!(source.recorded_at(), Some(10_300));
+
+        let end = source.recv_with(|_, _| ()).await.unwrap_err();
+        assert_eq!(end.kind(), io::ErrorKind::UnexpectedEof);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_gap_too_long_to_wait_for_is_invalid_input() {
+        let path = capture("slow", &[(1, 0, b"first"), (1, 1_000, b"second")]);
+        let mut source = ReplaySource::open(&path, Some(1e-300)).unwrap();
+
+        source.recv_with(|_, _| ()).await.unwrap();
+        let err = source.recv_with(|_, _| ()).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn a_torn_last_frame_ends_the_capture() {
+        let path = capture("torn", &[(1, 0, b"kept"), (1, 1, b"torn frame")]);
+        let len = std::fs::metadata(&path).unwrap().len();
+        std::fs::OpenOptions::new().write(true).open(&path).unwrap().set_len(len - 3).unwrap();
+
+        let mut reader = CaptureReader::open(&path).unwrap();
+        assert_eq!(reader.next_frame().unwrap().unwrap().payload, b"kept");
+        assert_eq!(reader.next_frame().unwrap(), None);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn frames_reach_the_file_once_the_flush_interval_passes() {
+        let path = capture("flush", &[]);
+        std::fs::remove_file(&path).unwrap();
+        let mut writer = CaptureWriter::create(&path).unwrap().with_flush_interval(Duration::from_secs(3600));
+        writer.write(1, 0, b"frame").unwrap();
+        writer.flush_if_due().unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 0);
+
+        writer = writer.with_flush_interval(Duration::ZERO);
+        writer.flush_if_due().unwrap();
+        let mut reader = CaptureReader::open(&path).unwrap();
+        assert_eq!(reader.next_frame().unwrap().unwrap().payload, b"frame");
+
+        drop(writer);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn each_run_gets_its_own_capture() {
+        let dir = std::env::temp_dir().join(format!("h278_capture_runs_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("frames.h2cp").to_string_lossy().into_owned();
+        let config = CaptureConfig { enabled: true, path, ..CaptureConfig::default() };
+        assert_eq!(config.run_path(1_700_000_000, 0), dir.join("frames-1700000000.h2cp"));
+        assert_eq!(config.run_path(1_700_000_000, 2), dir.join("frames-1700000000-2.h2cp"));
+
+        let (_, first) = config.create_writer(1_700_000_000).unwrap();
+        let (_, second) = config.create_writer(1_700_000_000).unwrap();
+        assert_eq!((first, second.clone()), (config.run_path(1_700_000_000, 0), config.run_path(1_700_000_000, 1)));
+        assert_eq!(CaptureWriter::create(&second).err().unwrap().kind(), io::ErrorKind::AlreadyExists);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn replay_options_come_from_the_command_line() {
+        assert_eq!(ReplayOptions::from_args(&[]), Ok(None));
+        assert_eq!(
+            ReplayOptions::from_args(&args(&["replay", "frames.h2cp"])),
+            Ok(Some(ReplayOptions { path: "frames.h2cp".to_string(), speed: Some(1.0), live_sinks: false })),
+        );
+        let fast = ReplayOptions::from_args(&args(&["replay", "f", "--speed", "max", "--live-sinks"])).unwrap().unwrap();
+        assert_eq!((fast.speed, fast.live_sinks), (None, true));
+        assert!(ReplayOptions::from_args(&args(&["replay"])).is_err());
+        assert!(ReplayOptions::from_args(&args(&["replay", "f", "--speed", "0"])).is_err());
+        assert!(ReplayOptions::from_args(&args(&["replay", "f", "--speed", "1e-300"])).is_err());
+        assert!(ReplayOptions::from_args(&args(&["replay", "f", "--speed", "inf"])).is_err());
+        assert!(ReplayOptions::from_args(&args(&["replay", "f", "--speed", "NaN"])).is_err());
+    }
+}
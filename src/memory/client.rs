@@ -0,0 +1,216 @@
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::memory::ring::SharedRing;
+
+#[cfg(windows)]
+use windows::Win32::System::Memory::{
+    MapViewOfFile, OpenFileMappingA, UnmapViewOfFile, VirtualQuery, FILE_MAP_ALL_ACCESS,
+    MEMORY_BASIC_INFORMATION,
+};
+#[cfg(windows)]
+use windows::Win32::Foundation::{CloseHandle, HANDLE};
+#[cfg(windows)]
+use windows::Win32::System::Threading::{OpenEventA, SetEvent, EVENT_MODIFY_STATE};
+#[cfg(windows)]
+use windows::core::PCSTR;
+
+#[cfg(unix)]
+use std::fs::OpenOptions;
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
+#[cfg(unix)]
+use std::ptr;
+#[cfg(unix)]
+use crate::memory::notify::NamedEvent;
+
+// How long `send` backs off between attempts while the ring is full
+const FULL_RING_BACKOFF: Duration = Duration::from_micros(50);
+
+// Producer side of `SharedMemoryServer`: opens the segment the server created, queues frames in
+// its ring and wakes the server after each one
+pub struct SharedMemoryClient {
+    #[cfg(windows)]
+    mapping_handle: HANDLE,
+    #[cfg(windows)]
+    event_handle: HANDLE,
+    #[cfg(unix)]
+    event: NamedEvent,
+    buffer_size: usize,
+    ptr: *mut u8,
+    ring: SharedRing,
+}
+
+impl SharedMemoryClient {
+    pub fn open(name: &str) -> io::Result<Self> {
+        #[cfg(unix)]
+        {
+            let path = format!("/dev/shm/{}", name);
+            let file = OpenOptions::new().read(true).write(true).open(&path)?;
+            let size = file.metadata()?.len() as usize;
+
+            let ptr = unsafe {
+                libc::mmap(
+                    ptr::null_mut(),
+                    size,
+                    libc::PROT_READ | libc::PROT_WRITE,
+                    libc::MAP_SHARED,
+                    file.as_raw_fd(),
+                    0,
+                ) as *mut u8
+            };
+
+            if ptr == libc::MAP_FAILED as *mut u8 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let setup = unsafe { SharedRing::attach(ptr, size) }
+                .and_then(|ring| Ok((ring, NamedEvent::open(name)?)));
+            let (ring, event) = match setup {
+                Ok(setup) => setup,
+                Err(e) => {
+                    unsafe { libc::munmap(ptr as *mut libc::c_void, size) };
+                    return Err(e);
+                }
+            };
+
+            Ok(SharedMemoryClient {
+                event,
+                buffer_size: size,
+                ptr,
+                ring,
+            })
+        }
+
+        #[cfg(windows)]
+        {
+            unsafe {
+                let name_cstr = format!("{}\0", name);
+                let event_name = format!("{}_event\0", name);
+
+                let mapping_handle = OpenFileMappingA(
+                    FILE_MAP_ALL_ACCESS.0,
+                    false,
+                    PCSTR(name_cstr.as_ptr()),
+                )?;
+
+                let event_handle = match OpenEventA(
+                    EVENT_MODIFY_STATE,
+                    false,
+                    PCSTR(event_name.as_ptr()),
+                ) {
+                    Ok(handle) => handle,
+                    Err(e) => {
+                        let _ = CloseHandle(mapping_handle);
+                        return Err(e.into());
+                    }
+                };
+                let close_handles = || {
+                    let _ = CloseHandle(mapping_handle);
+                    let _ = CloseHandle(event_handle);
+                };
+
+                // Map the whole section and ask the OS how big it is
+                let ptr = MapViewOfFile(mapping_handle, FILE_MAP_ALL_ACCESS, 0, 0, 0);
+                if ptr.Value.is_null() {
+                    let e = io::Error::last_os_error();
+                    close_handles();
+                    return Err(e);
+                }
+
+                let mut info = MEMORY_BASIC_INFORMATION::default();
+                VirtualQuery(
+                    Some(ptr.Value as *const _),
+                    &mut info,
+                    std::mem::size_of::<MEMORY_BASIC_INFORMATION>(),
+                );
+                let size = info.RegionSize;
+
+                let ring = match SharedRing::attach(ptr.Value as *mut u8, size) {
+                    Ok(ring) => ring,
+                    Err(e) => {
+                        let _ = UnmapViewOfFile(ptr);
+                        close_handles();
+                        return Err(e);
+                    }
+                };
+
+                Ok(SharedMemoryClient {
+                    mapping_handle,
+                    event_handle,
+                    buffer_size: size,
+                    ptr: ptr.Value as *mut u8,
+                    ring,
+                })
+            }
+        }
+    }
+
+    pub fn max_payload(&self) -> usize {
+        self.ring.max_payload()
+    }
+
+    // Queues one frame and wakes the server. Fails with `WouldBlock` if the ring is full.
+    pub fn try_send(&self, client_id: u32, payload: &[u8]) -> io::Result<()> {
+        self.ring.push(client_id, payload)?;
+        self.notify()
+    }
+
+    // Like `try_send`, but waits for the server to free a slot for up to `timeout`
+    pub fn send_timeout(&self, client_id: u32, payload: &[u8], timeout: Duration) -> io::Result<()> {
+        // No deadline when the timeout is too large to represent
+        let deadline = Instant::now().checked_add(timeout);
+        loop {
+            match self.try_send(client_id, payload) {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock && deadline.is_none_or(|d| Instant::now() < d) => {
+                    // Make sure the server is awake to drain, then give it a moment
+                    self.notify()?;
+                    thread::sleep(FULL_RING_BACKOFF);
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, "ring stayed full"));
+                }
+                result => return result,
+            }
+        }
+    }
+
+    pub fn send(&self, client_id: u32, payload: &[u8]) -> io::Result<()> {
+        self.send_timeout(client_id, payload, Duration::MAX)
+    }
+
+    fn notify(&self) -> io::Result<()> {
+        #[cfg(unix)]
+        {
+            self.event.signal()
+        }
+
+        #[cfg(windows)]
+        unsafe {
+            SetEvent(self.event_handle)?;
+            Ok(())
+        }
+    }
+}
+
+impl Drop for SharedMemoryClient {
+    fn drop(&mut self) {
+        unsafe {
+            #[cfg(windows)]
+            {
+                windows::Win32::System::Memory::UnmapViewOfFile(
+                    windows::Win32::System::Memory::MEMORY_MAPPED_VIEW_ADDRESS {
+                        Value: self.ptr as _
+                    }
+                );
+                windows::Win32::Foundation::CloseHandle(self.mapping_handle);
+                windows::Win32::Foundation::CloseHandle(self.event_handle);
+            }
+
+            #[cfg(unix)]
+            {
+                libc::munmap(self.ptr as *mut libc::c_void, self.buffer_size);
+            }
+        }
+    }
+}
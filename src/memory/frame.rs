@@ -0,0 +1,81 @@
+use std::io;
+use std::ptr;
+
+// Header a producer writes in front of every payload:
+//
+//   0   sequence (u64)   ring position the frame was written for
+//   8   length (u32)     payload bytes that follow the header
+//   12  client id (u32)
+//   16  checksum (u32)   CRC32 of the payload
+//   20  reserved (u32)
+pub const FRAME_HEADER_SIZE: usize = 24;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameHeader {
+    pub sequence: u64,
+    pub len: u32,
+    pub client_id: u32,
+    pub checksum: u32,
+}
+
+impl FrameHeader {
+    pub fn new(sequence: u64, client_id: u32, payload: &[u8]) -> Self {
+        FrameHeader {
+            sequence,
+            len: payload.len() as u32,
+            client_id,
+            checksum: crc32fast::hash(payload),
+        }
+    }
+
+    /// # Safety
+    /// `dst` must be valid for `FRAME_HEADER_SIZE` bytes of writes.
+    pub unsafe fn write(&self, dst: *mut u8) {
+        ptr::write_unaligned(dst as *mut u64, self.sequence);
+        ptr::write_unaligned(dst.add(8) as *mut u32, self.len);
+        ptr::write_unaligned(dst.add(12) as *mut u32, self.client_id);
+        ptr::write_unaligned(dst.add(16) as *mut u32, self.checksum);
+        ptr::write_unaligned(dst.add(20) as *mut u32, 0);
+    }
+
+    /// # Safety
+    /// `src` must be valid for `FRAME_HEADER_SIZE` bytes of reads.
+    pub unsafe fn read(src: *const u8) -> Self {
+        FrameHeader {
+            sequence: ptr::read_unaligned(src as *const u64),
+            len: ptr::read_unaligned(src.add(8) as *const u32),
+            client_id: ptr::read_unaligned(src.add(12) as *const u32),
+            checksum: ptr::read_unaligned(src.add(16) as *const u32),
+        }
+    }
+
+    // Checks everything that can be checked before touching the payload, so a bad length can
+    // never make the reader run past the slot
+    pub fn validate(&self, expected_sequence: u64, capacity: usize) -> io::Result<()> {
+        if self.len as usize > capacity {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("frame length {} exceeds capacity of {} bytes", self.len, capacity),
+            ));
+        }
+        if self.sequence != expected_sequence {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("frame sequence {} does not match slot position {}", self.sequence, expected_sequence),
+            ));
+        }
+        Ok(())
+    }
+
+    // Catches payloads that were still being written, or rewritten, after the frame was committed
+    pub fn verify_payload(&self, payload: &[u8]) -> io::Result<()> {
+        let checksum = crc32fast::hash(payload);
+        if checksum != self.checksum {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("frame {} checksum {:08x} does not match header {:08x}", self.sequence, checksum, self.checksum),
+            ));
+        }
+        Ok(())
+    }
+}
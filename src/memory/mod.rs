@@ -1 +1,9 @@
-pub mod server;
\ No newline at end of file
+pub mod server;
+pub mod client;
+pub mod frame;
+pub mod ring;
+pub mod transport;
+pub mod mux;
+pub mod capture;
+#[cfg(unix)]
+pub mod notify;
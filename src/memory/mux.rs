@@ -0,0 +1,128 @@
+use serde::Deserialize;
+use std::future::{poll_fn, Future};
+use std::io;
+use std::task::Poll;
+use crate::memory::ring::RingFrame;
+use crate::memory::server::{SegmentOptions, SharedMemoryServer};
+use crate::memory::transport::FrameSource;
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct SegmentConfig {
+    pub name: String,
+    pub size: usize,
+    #[serde(flatten)]
+    pub options: SegmentOptions,
+}
+
+// One server over several segments, typically one per producer or client group, so a producer
+// that floods its ring only ever fills its own slots. Segments are drained round robin and a wait
+// returns as soon as any of them is signalled.
+//
+// Neither named semaphores nor Windows events can go into epoll, so every segment keeps one
+// blocking wait on Tokio's blocking pool and the mux polls them all together.
+pub struct SharedMemoryMux {
+    segments: Vec<(String, SharedMemoryServer)>,
+    // Segment the next drain starts from
+    next: usize,
+}
+
+impl SharedMemoryMux {
+    pub fn new() -> Self {
+        SharedMemoryMux { segments: Vec::new(), next: 0 }
+    }
+
+    pub fn open(configs: &[SegmentConfig]) -> io::Result<Self> {
+        let mut mux = Self::new();
+        for config in configs {
+            mux.add(&config.name, config.size, &config.options)?;
+        }
+        Ok(mux)
+    }
+
+    // Creates and owns another segment; returns its index
+    pub fn add(&mut self, name: &str, size: usize, options: &SegmentOptions) -> io::Result<usize> {
+        if self.segments.iter().any(|(existing, _)| existing == name) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("segment {} is already part of this server", name),
+            ));
+        }
+        let server = SharedMemoryServer::with_options(name, size, options)?;
+        self.segments.push((name.to_string(), server));
+        Ok(self.segments.len() - 1)
+    }
+
+    pub fn len(&self) -> usize {
+        self.segments.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    pub fn segment_name(&self, index: usize) -> Option<&str> {
+        self.segments.get(index).map(|(name, _)| name.as_str())
+    }
+
+    // The next frame from whichever segment's turn it is, with that segment's index. A segment
+    // gets one frame per turn, so a busy one can't starve the rest.
+    pub fn next_frame(&mut self) -> io::Result<Option<(usize, RingFrame<'_>)>> {
+        let count = self.segments.len();
+        let Some(index) = (0..count)
+            .map(|offset| (self.next + offset) % count)
+            .find(|&index| self.segments[index].1.has_frame())
+        else {
+            return Ok(None);
+        };
+
+        self.next = (index + 1) % count;
+        Ok(self.segments[index].1.next_frame()?.map(|frame| (index, frame)))
+    }
+
+    // Waits until any segment is signalled and returns its index. Waits on the other segments
+    // stay in flight, so their signals are picked up by the next call.
+    pub async fn wait_async(&mut self) -> io::Result<usize> {
+        if self.segments.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "no segments to wait on"));
+        }
+
+        // The segment whose abandoned slot can be skipped first, if any, wakes the wait too
+        let mut stalled = self.segments.iter()
+            .enumerate()
+            .filter_map(|(index, (_, server))| server.stall_deadline().map(|deadline| (deadline, index)))
+            .min()
+            .map(|(deadline, index)| (Box::pin(tokio::time::sleep_until(deadline.into())), index));
+
+        poll_fn(|cx| {
+            for (index, (_, server)) in self.segments.iter_mut().enumerate() {
+                if let Poll::Ready(result) = server.poll_wait(cx) {
+                    return Poll::Ready(result.map(|()| index));
+                }
+            }
+            if let Some((timeout, index)) = stalled.as_mut() {
+                if timeout.as_mut().poll(cx).is_ready() {
+                    return Poll::Ready(Ok(*index));
+                }
+            }
+            Poll::Pending
+        })
+        .await
+    }
+}
+
+impl Default for SharedMemoryMux {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameSource for SharedMemoryMux {
+    async fn recv_with<R>(&mut self, f: impl FnOnce(u32, &[u8]) -> R) -> io::Result<R> {
+        loop {
+            if let Some((_, frame)) = self.next_frame()? {
+                return Ok(f(frame.client_id(), &frame));
+            }
+            self.wait_async().await?;
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use std::ffi::CString;
+use std::io;
+
+// Unix counterpart of the Windows auto-reset event the server waits on. It is a named POSIX
+// semaphore (`/dev/shm/sem.<name>_event` on Linux), so any process that knows the segment name
+// can open it and wake the server. Waiting drains the count afterwards, which makes several
+// signals that arrive before the server wakes collapse into one, like `SetEvent` does.
+pub struct NamedEvent {
+    sem: *mut libc::sem_t,
+}
+
+// POSIX semaphores are made to be posted and waited on from any thread
+unsafe impl Send for NamedEvent {}
+unsafe impl Sync for NamedEvent {}
+
+fn event_name(name: &str) -> io::Result<CString> {
+    CString::new(format!("/{}_event", name))
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "event name contains a NUL byte"))
+}
+
+impl NamedEvent {
+    pub fn create(name: &str) -> io::Result<Self> {
+        Self::open_with(name, libc::O_CREAT)
+    }
+
+    // Producers open the server's event by name
+    pub fn open(name: &str) -> io::Result<Self> {
+        Self::open_with(name, 0)
+    }
+
+    fn open_with(name: &str, flags: libc::c_int) -> io::Result<Self> {
+        let name = event_name(name)?;
+        let sem = unsafe {
+            libc::sem_open(name.as_ptr(), flags, 0o666 as libc::c_uint, 0 as libc::c_uint)
+        };
+
+        if sem == libc::SEM_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(NamedEvent { sem })
+    }
+
+    // Removes the name so later `open`s fail; handles that are already open keep working
+    pub fn unlink(name: &str) -> io::Result<()> {
+        let name = event_name(name)?;
+        if unsafe { libc::sem_unlink(name.as_ptr()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    pub fn signal(&self) -> io::Result<()> {
+        if unsafe { libc::sem_post(self.sem) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    pub fn wait(&self) -> io::Result<()> {
+        loop {
+            if unsafe { libc::sem_wait(self.sem) } == 0 {
+                break;
+            }
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err);
+            }
+        }
+
+        // Auto-reset: swallow signals that piled up while we weren't waiting
+        while self.try_wait()? {}
+        Ok(())
+    }
+
+    pub fn try_wait(&self) -> io::Result<bool> {
+        if unsafe { libc::sem_trywait(self.sem) } == 0 {
+            return Ok(true);
+        }
+
+        let err = io::Error::last_os_error();
+        match err.raw_os_error() {
+            Some(libc::EAGAIN) => Ok(false),
+            Some(libc::EINTR) => self.try_wait(),
+            _ => Err(err),
+        }
+    }
+}
+
+impl Drop for NamedEvent {
+    fn drop(&mut self) {
+        unsafe {
+            libc::sem_close(self.sem);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn signal_from_another_handle_wakes_waiter() {
+        let name = format!("h278_notify_wake_{}", std::process::id());
+        let server = NamedEvent::create(&name).unwrap();
+
+        let producer = thread::spawn({
+            let name = name.clone();
+            move || {
+                let client = NamedEvent::open(&name).unwrap();
+                thread::sleep(Duration::from_millis(20));
+                client.signal().unwrap();
+            }
+        });
+
+        server.wait().unwrap();
+        producer.join().unwrap();
+        NamedEvent::unlink(&name).unwrap();
+    }
+
+    #[test]
+    fn pending_signals_collapse_into_one_wake() {
+        let name = format!("h278_notify_reset_{}", std::process::id());
+        let server = NamedEvent::create(&name).unwrap();
+        let client = NamedEvent::open(&name).unwrap();
+
+        client.signal().unwrap();
+        client.signal().unwrap();
+        client.signal().unwrap();
+
+        server.wait().unwrap();
+        assert!(!server.try_wait().unwrap());
+        NamedEvent::unlink(&name).unwrap();
+    }
+}
@@ -0,0 +1,587 @@
+use std::io;
+use std::ptr;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use crate::memory::frame::{FrameHeader, FRAME_HEADER_SIZE};
+
+// Layout of the shared segment:
+//
+//   0    magic (u32), version (u32), slot_count (u32), slot_size (u32), owner pid (u32)
+//   64   tail (u64)   next position producers claim, on its own cache line
+//   128  head (u64)   next position the server reads, on its own cache line
+//   192  slot_count slots of slot_size bytes each
+//
+// Each slot starts with its state (u64), then a `FrameHeader` and the payload. Producers and the
+// consumer coordinate through the per-slot state (a bounded MPMC queue in the style of Vyukov's),
+// so any number of producer processes can enqueue without locks and a frame only becomes visible
+// once it has been committed. The frame header is validated again on the way out.
+//
+// A producer must not touch a slot between committing it and the consumer releasing it. The
+// payload checksum catches frames torn before the commit, not writes made after it.
+//
+// A producer that dies between claiming a slot and committing it would stall the consumer at that
+// slot forever. Once a claimed slot has stayed uncommitted for `ABANDONED_SLOT_TIMEOUT` the
+// consumer takes it back and skips it; a producer that was only slow then fails its commit.
+pub const RING_MAGIC: u32 = 0x4832_3738; // "H278"
+pub const RING_VERSION: u32 = 3;
+pub const HEADER_SIZE: usize = 192;
+// Leading header bytes `RingHeader::parse` reads
+pub const RING_HEADER_PREFIX: usize = 20;
+pub const SLOT_HEADER_SIZE: usize = 8 + FRAME_HEADER_SIZE;
+pub const DEFAULT_SLOT_SIZE: usize = 64 * 1024;
+
+const MAGIC_OFFSET: usize = 0;
+const VERSION_OFFSET: usize = 4;
+const SLOT_COUNT_OFFSET: usize = 8;
+const SLOT_SIZE_OFFSET: usize = 12;
+const OWNER_PID_OFFSET: usize = 16;
+const TAIL_OFFSET: usize = 64;
+const HEAD_OFFSET: usize = 128;
+
+// Far longer than any producer needs to copy one slot's worth of payload
+pub const ABANDONED_SLOT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// The identifying fields of a ring header, read from a segment before deciding whether to reuse it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RingHeader {
+    pub magic: u32,
+    pub version: u32,
+    pub owner_pid: u32,
+}
+
+impl RingHeader {
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        let field = |offset: usize| Some(u32::from_ne_bytes(bytes.get(offset..offset + 4)?.try_into().ok()?));
+        Some(RingHeader {
+            magic: field(MAGIC_OFFSET)?,
+            version: field(VERSION_OFFSET)?,
+            owner_pid: field(OWNER_PID_OFFSET)?,
+        })
+    }
+
+    // Whether a server of this version laid the header out. A zero magic is a segment that was
+    // still being set up, possibly before its version was written.
+    pub fn is_ours(&self) -> bool {
+        match self.magic {
+            RING_MAGIC => self.version == RING_VERSION,
+            0 => self.version == RING_VERSION || self.version == 0,
+            _ => false,
+        }
+    }
+}
+
+pub struct SharedRing {
+    base: *mut u8,
+    slot_count: u64,
+    slot_size: usize,
+    // Consumer side: the claimed but uncommitted position at the head, and when it was first seen
+    stalled: Option<(u64, Instant)>,
+    abandon_timeout: Duration,
+}
+
+impl SharedRing {
+    /// Lays out a fresh ring over `size` bytes at `base`. Only the server does this.
+    ///
+    /// # Safety
+    /// `base` must point to at least `size` writable bytes, aligned to 64, that stay mapped for
+    /// as long as the ring is used.
+    pub unsafe fn init(base: *mut u8, size: usize, slot_size: usize) -> io::Result<Self> {
+        if slot_size <= SLOT_HEADER_SIZE || !slot_size.is_multiple_of(64) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("slot size {} must be a multiple of 64 above {}", slot_size, SLOT_HEADER_SIZE),
+            ));
+        }
+        // With a single slot "committed" and "free for the next lap" would look the same
+        let slot_count = size.saturating_sub(HEADER_SIZE) / slot_size;
+        if slot_count < 2 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("segment of {} bytes is too small for two {} byte slots", size, slot_size),
+            ));
+        }
+
+        let ring = SharedRing::with_geometry(base, slot_count as u64, slot_size);
+
+        // Hide the ring from producers while it is being rebuilt
+        ring.u32_at(MAGIC_OFFSET).store(0, Ordering::Release);
+        ring.u32_at(VERSION_OFFSET).store(RING_VERSION, Ordering::Relaxed);
+        ring.u32_at(SLOT_COUNT_OFFSET).store(slot_count as u32, Ordering::Relaxed);
+        ring.u32_at(SLOT_SIZE_OFFSET).store(slot_size as u32, Ordering::Relaxed);
+        ring.u32_at(OWNER_PID_OFFSET).store(std::process::id(), Ordering::Relaxed);
+        ring.u64_at(TAIL_OFFSET).store(0, Ordering::Relaxed);
+        ring.u64_at(HEAD_OFFSET).store(0, Ordering::Relaxed);
+        for pos in 0..ring.slot_count {
+            ring.sequence(pos).store(pos, Ordering::Relaxed);
+        }
+        ring.u32_at(MAGIC_OFFSET).store(RING_MAGIC, Ordering::Release);
+
+        Ok(ring)
+    }
+
+    /// Joins a ring the server already laid out. Producers do this.
+    ///
+    /// # Safety
+    /// Same as `init`.
+    pub unsafe fn attach(base: *mut u8, size: usize) -> io::Result<Self> {
+        if size < HEADER_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "segment is smaller than the ring header"));
+        }
+
+        let header = SharedRing::with_geometry(base, 0, 0);
+        if header.u32_at(MAGIC_OFFSET).load(Ordering::Acquire) != RING_MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "segment has no ring (bad magic)"));
+        }
+        let version = header.u32_at(VERSION_OFFSET).load(Ordering::Relaxed);
+        if version != RING_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported ring version {}", version),
+            ));
+        }
+
+        let slot_count = header.u32_at(SLOT_COUNT_OFFSET).load(Ordering::Relaxed) as usize;
+        let slot_size = header.u32_at(SLOT_SIZE_OFFSET).load(Ordering::Relaxed) as usize;
+        // Slot sequences are atomic u64s, so slots must keep them 8-byte aligned
+        if slot_size <= SLOT_HEADER_SIZE || !slot_size.is_multiple_of(8) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("invalid ring slot size {}", slot_size)));
+        }
+        if slot_count < 2 || HEADER_SIZE + slot_count * slot_size > size {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "ring geometry does not fit the segment"));
+        }
+
+        Ok(SharedRing::with_geometry(base, slot_count as u64, slot_size))
+    }
+
+    fn with_geometry(base: *mut u8, slot_count: u64, slot_size: usize) -> Self {
+        SharedRing { base, slot_count, slot_size, stalled: None, abandon_timeout: ABANDONED_SLOT_TIMEOUT }
+    }
+
+    pub fn max_payload(&self) -> usize {
+        self.slot_size - SLOT_HEADER_SIZE
+    }
+
+    // Enqueues one frame. Fails with `WouldBlock` when every slot is taken.
+    pub fn push(&self, client_id: u32, payload: &[u8]) -> io::Result<()> {
+        if payload.len() > self.max_payload() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("frame of {} bytes exceeds slot payload of {}", payload.len(), self.max_payload()),
+            ));
+        }
+
+        let pos = self.claim()?;
+        self.commit(pos, client_id, payload)
+    }
+
+    // Reserves the next free position for this producer
+    fn claim(&self) -> io::Result<u64> {
+        let tail = self.u64_at(TAIL_OFFSET);
+        let mut pos = tail.load(Ordering::Relaxed);
+        loop {
+            let seq = self.sequence(pos).load(Ordering::Acquire);
+            let diff = seq as i64 - pos as i64;
+
+            if diff == 0 {
+                match tail.compare_exchange_weak(pos, pos + 1, Ordering::Relaxed, Ordering::Relaxed) {
+                    Ok(_) => return Ok(pos),
+                    Err(current) => pos = current,
+                }
+            } else if diff < 0 {
+                return Err(io::Error::new(io::ErrorKind::WouldBlock, "ring is full"));
+            } else {
+                pos = tail.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    // Fills a claimed slot and publishes it. Fails with `TimedOut` if the consumer gave up on the
+    // slot in the meantime.
+    fn commit(&self, pos: u64, client_id: u32, payload: &[u8]) -> io::Result<()> {
+        unsafe {
+            let slot = self.slot(pos);
+            FrameHeader::new(pos, client_id, payload).write(slot.add(8));
+            ptr::copy_nonoverlapping(payload.as_ptr(), slot.add(SLOT_HEADER_SIZE), payload.len());
+        }
+        // Publish: the consumer only reads the slot once it sees pos + 1
+        self.sequence(pos)
+            .compare_exchange(pos, pos + 1, Ordering::Release, Ordering::Relaxed)
+            .map(|_| ())
+            .map_err(|_| {
+                io::Error::new(io::ErrorKind::TimedOut, format!("slot {} was reclaimed before it was committed", pos))
+            })
+    }
+
+    // Whether `pop_frame` has something to hand out: a committed frame (valid or not), or an
+    // abandoned slot to skip
+    pub fn has_frame(&self) -> bool {
+        let pos = self.u64_at(HEAD_OFFSET).load(Ordering::Relaxed);
+        self.sequence(pos).load(Ordering::Acquire) == pos + 1
+            || self.stall_deadline().is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    // When the slot holding up the head will be skipped, if one is. Waits for new frames should
+    // end by then, since the producer that claimed the slot may never signal again.
+    pub fn stall_deadline(&self) -> Option<Instant> {
+        self.stalled.map(|(_, since)| since + self.abandon_timeout)
+    }
+
+    // Dequeues the oldest committed frame, if any, as an owned copy. The payload is copied out of
+    // the slot before its checksum is checked, so the check holds for the bytes returned even if a
+    // misbehaving producer keeps writing to the slot.
+    pub fn pop(&mut self) -> io::Result<Option<(u32, Vec<u8>)>> {
+        let Some((pos, header)) = self.next_committed()? else {
+            return Ok(None);
+        };
+
+        let len = header.len as usize;
+        let mut payload = Vec::with_capacity(len);
+        unsafe {
+            ptr::copy_nonoverlapping(self.slot(pos).add(SLOT_HEADER_SIZE), payload.as_mut_ptr(), len);
+            payload.set_len(len);
+        }
+        self.release(pos);
+
+        header.verify_payload(&payload)?;
+        Ok(Some((header.client_id, payload)))
+    }
+
+    // Borrows the oldest committed frame in place. The slot stays reserved until the returned
+    // guard is dropped, and `&mut self` keeps it the only consumer while it lives.
+    //
+    // The checksum is checked on the shared bytes, so this relies on producers leaving a slot
+    // alone between committing it and the server releasing it, which `push` does. Use `pop` when
+    // producers can't be trusted to keep to that.
+    pub fn pop_frame(&mut self) -> io::Result<Option<RingFrame<'_>>> {
+        let Some((pos, header)) = self.next_committed()? else {
+            return Ok(None);
+        };
+
+        let payload = unsafe {
+            std::slice::from_raw_parts(self.slot(pos).add(SLOT_HEADER_SIZE), header.len as usize)
+        };
+        if let Err(e) = header.verify_payload(payload) {
+            self.release(pos);
+            return Err(e);
+        }
+
+        Ok(Some(RingFrame { ring: self, pos, client_id: header.client_id, len: header.len as usize }))
+    }
+
+    // Finds the committed frame at the head and checks its header, leaving the slot reserved
+    fn next_committed(&mut self) -> io::Result<Option<(u64, FrameHeader)>> {
+        let pos = self.u64_at(HEAD_OFFSET).load(Ordering::Relaxed);
+        let seq = self.sequence(pos).load(Ordering::Acquire);
+
+        if seq != pos + 1 {
+            return self.check_stalled(pos, seq).map(|()| None);
+        }
+        self.stalled = None;
+
+        let header = unsafe { FrameHeader::read(self.slot(pos).add(8)) };
+        if let Err(e) = header.validate(pos, self.max_payload()) {
+            // Hand a bad slot straight back so one broken frame can't stall the ring
+            self.release(pos);
+            return Err(e);
+        }
+        Ok(Some((pos, header)))
+    }
+
+    // Tracks a head slot that was claimed but not committed, and skips it once the timeout passes
+    fn check_stalled(&mut self, pos: u64, seq: u64) -> io::Result<()> {
+        let claimed = seq == pos && self.u64_at(TAIL_OFFSET).load(Ordering::Relaxed) > pos;
+        let since = match self.stalled {
+            _ if !claimed => {
+                self.stalled = None;
+                return Ok(());
+            }
+            Some((stalled, since)) if stalled == pos => since,
+            _ => {
+                self.stalled = Some((pos, Instant::now()));
+                return Ok(());
+            }
+        };
+        if since.elapsed() < self.abandon_timeout {
+            return Ok(());
+        }
+
+        // The producer may still commit at the last moment, in which case the frame is served
+        let next_lap = pos + self.slot_count;
+        if self.sequence(pos).compare_exchange(pos, next_lap, Ordering::AcqRel, Ordering::Relaxed).is_err() {
+            return Ok(());
+        }
+        self.u64_at(HEAD_OFFSET).store(pos + 1, Ordering::Relaxed);
+        self.stalled = None;
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("slot {} was claimed but not committed within {:?}; skipped", pos, self.abandon_timeout),
+        ))
+    }
+
+    // Gives the slot at `pos` back to producers for the next lap
+    fn release(&self, pos: u64) {
+        self.sequence(pos).store(pos + self.slot_count, Ordering::Release);
+        self.u64_at(HEAD_OFFSET).store(pos + 1, Ordering::Relaxed);
+    }
+
+    fn slot(&self, pos: u64) -> *mut u8 {
+        let index = (pos % self.slot_count) as usize;
+        unsafe { self.base.add(HEADER_SIZE + index * self.slot_size) }
+    }
+
+    fn sequence(&self, pos: u64) -> &AtomicU64 {
+        unsafe { &*(self.slot(pos) as *const AtomicU64) }
+    }
+
+    fn u32_at(&self, offset: usize) -> &AtomicU32 {
+        unsafe { &*(self.base.add(offset) as *const AtomicU32) }
+    }
+
+    fn u64_at(&self, offset: usize) -> &AtomicU64 {
+        unsafe { &*(self.base.add(offset) as *const AtomicU64) }
+    }
+}
+
+// A committed frame still inside its slot. Derefs to the payload bytes in the mapping, which only
+// stay as checked while producers keep to the contract above.
+pub struct RingFrame<'a> {
+    ring: &'a SharedRing,
+    pos: u64,
+    client_id: u32,
+    len: usize,
+}
+
+impl RingFrame<'_> {
+    pub fn client_id(&self) -> u32 {
+        self.client_id
+    }
+}
+
+impl std::ops::Deref for RingFrame<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ring.slot(self.pos).add(SLOT_HEADER_SIZE), self.len) }
+    }
+}
+
+impl Drop for RingFrame<'_> {
+    fn drop(&mut self) {
+        self.ring.release(self.pos);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::alloc::{alloc_zeroed, dealloc, Layout};
+
+    struct Region {
+        ptr: *mut u8,
+        layout: Layout,
+    }
+
+    impl Region {
+        fn new(size: usize) -> Self {
+            let layout = Layout::from_size_align(size, 64).unwrap();
+            Region { ptr: unsafe { alloc_zeroed(layout) }, layout }
+        }
+    }
+
+    impl Drop for Region {
+        fn drop(&mut self) {
+            unsafe { dealloc(self.ptr, self.layout) }
+        }
+    }
+
+    #[test]
+    fn frames_come_out_in_order_across_wraparound() {
+        let region = Region::new(HEADER_SIZE + 4 * 128);
+        let mut ring = unsafe { SharedRing::init(region.ptr, region.layout.size(), 128).unwrap() };
+        let producer = unsafe { SharedRing::attach(region.ptr, region.layout.size()).unwrap() };
+
+        for lap in 0..5u32 {
+            for i in 0..4u32 {
+                producer.push(i, &[lap as u8; 3]).unwrap();
+            }
+            assert_eq!(producer.push(9, b"x").unwrap_err().kind(), io::ErrorKind::WouldBlock);
+
+            for i in 0..4u32 {
+                assert_eq!(ring.pop().unwrap(), Some((i, vec![lap as u8; 3])));
+            }
+            assert_eq!(ring.pop().unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn borrowed_frame_holds_its_slot_until_dropped() {
+        let region = Region::new(HEADER_SIZE + 2 * 128);
+        let mut ring = unsafe { SharedRing::init(region.ptr, region.layout.size(), 128).unwrap() };
+        let producer = unsafe { SharedRing::attach(region.ptr, region.layout.size()).unwrap() };
+
+        producer.push(3, b"first").unwrap();
+        producer.push(3, b"queued").unwrap();
+        let frame = ring.pop_frame().unwrap().unwrap();
+        assert_eq!((frame.client_id(), &*frame), (3, &b"first"[..]));
+        assert_eq!(producer.push(3, b"second").unwrap_err().kind(), io::ErrorKind::WouldBlock);
+
+        drop(frame);
+        producer.push(3, b"second").unwrap();
+        assert_eq!(ring.pop().unwrap(), Some((3, b"queued".to_vec())));
+        assert_eq!(ring.pop().unwrap(), Some((3, b"second".to_vec())));
+    }
+
+    #[test]
+    fn corrupt_frames_are_rejected_without_stalling_the_ring() {
+        let region = Region::new(HEADER_SIZE + 4 * 128);
+        let mut ring = unsafe { SharedRing::init(region.ptr, region.layout.size(), 128).unwrap() };
+        let slot = |index: usize| unsafe { region.ptr.add(HEADER_SIZE + index * 128 + 8) };
+
+        for _ in 0..4 {
+            ring.push(7, b"payload").unwrap();
+        }
+        // Length past the slot, a stale sequence and a payload changed after commit
+        unsafe {
+            ptr::write_unaligned(slot(0).add(8) as *mut u32, u32::MAX);
+            ptr::write_unaligned(slot(1) as *mut u64, 42);
+            *slot(2).add(FRAME_HEADER_SIZE) ^= 0xff;
+        }
+
+        for _ in 0..3 {
+            assert_eq!(ring.pop().unwrap_err().kind(), io::ErrorKind::InvalidData);
+        }
+        assert_eq!(ring.pop().unwrap(), Some((7, b"payload".to_vec())));
+        assert_eq!(ring.pop().unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_oversized_frames_and_foreign_segments() {
+        let region = Region::new(HEADER_SIZE + 2 * 128);
+        assert!(unsafe { SharedRing::attach(region.ptr, region.layout.size()) }.is_err());
+
+        let ring = unsafe { SharedRing::init(region.ptr, region.layout.size(), 128).unwrap() };
+        let err = ring.push(0, &[0; 128 - SLOT_HEADER_SIZE + 1]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        // A header whose slots would leave the sequence counters misaligned
+        ring.u32_at(SLOT_SIZE_OFFSET).store(124, Ordering::Relaxed);
+        let err = unsafe { SharedRing::attach(region.ptr, region.layout.size()) }.err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn slots_abandoned_by_a_producer_are_skipped() {
+        let region = Region::new(HEADER_SIZE + 4 * 128);
+        let mut ring = unsafe { SharedRing::init(region.ptr, region.layout.size(), 128).unwrap() };
+        ring.abandon_timeout = Duration::from_millis(20);
+        let producer = unsafe { SharedRing::attach(region.ptr, region.layout.size()).unwrap() };
+
+        // A producer claims a slot and stops; the one after it commits
+        let abandoned = producer.claim().unwrap();
+        producer.push(2, b"behind").unwrap();
+        assert_eq!(ring.pop().unwrap(), None);
+        assert!(!ring.has_frame());
+        let deadline = ring.stall_deadline().unwrap();
+
+        std::thread::sleep(deadline - Instant::now());
+        assert!(ring.has_frame());
+        assert_eq!(ring.pop().unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(ring.pop().unwrap(), Some((2, b"behind".to_vec())));
+        assert_eq!(ring.stall_deadline(), None);
+
+        // Had it only been slow, its commit now fails instead of landing in a reused slot
+        let err = producer.commit(abandoned, 1, b"late").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        producer.push(3, b"next").unwrap();
+        assert_eq!(ring.pop().unwrap(), Some((3, b"next".to_vec())));
+    }
+
+    // Producer half of the multi-process test, run by it with `--ignored`
+    #[cfg(unix)]
+    #[test]
+    #[ignore = "launched by ring_survives_concurrent_producer_processes"]
+    fn ring_stress_producer_child() {
+        let path = std::env::var("H278_RING_PATH").unwrap();
+        let id: u32 = std::env::var("H278_RING_PRODUCER").unwrap().parse().unwrap();
+        let count: u32 = std::env::var("H278_RING_COUNT").unwrap().parse().unwrap();
+
+        let (ptr, size) = map_file(&path);
+        let ring = unsafe { SharedRing::attach(ptr, size).unwrap() };
+        for n in 0..count {
+            let payload = n.to_le_bytes();
+            while let Err(e) = ring.push(id, &payload) {
+                assert_eq!(e.kind(), io::ErrorKind::WouldBlock);
+                std::thread::yield_now();
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    fn map_file(path: &str) -> (*mut u8, usize) {
+        use std::os::unix::io::AsRawFd;
+
+        let file = std::fs::OpenOptions::new().read(true).write(true).open(path).unwrap();
+        let size = file.metadata().unwrap().len() as usize;
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        assert_ne!(ptr, libc::MAP_FAILED);
+        (ptr as *mut u8, size)
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn ring_survives_concurrent_producer_processes() {
+        const PRODUCERS: u32 = 4;
+        const COUNT: u32 = 5_000;
+
+        let path = std::env::temp_dir().join(format!("h278_ring_stress_{}", std::process::id()));
+        let size = HEADER_SIZE + 8 * 64;
+        std::fs::write(&path, vec![0u8; size]).unwrap();
+        let (ptr, size) = map_file(path.to_str().unwrap());
+        let mut ring = unsafe { SharedRing::init(ptr, size, 64).unwrap() };
+
+        let children: Vec<_> = (0..PRODUCERS)
+            .map(|id| {
+                std::process::Command::new(std::env::current_exe().unwrap())
+                    .args(["--exact", "memory::ring::tests::ring_stress_producer_child", "--ignored", "--test-threads=1"])
+                    .env("H278_RING_PATH", &path)
+                    .env("H278_RING_PRODUCER", id.to_string())
+                    .env("H278_RING_COUNT", COUNT.to_string())
+                    .stdout(std::process::Stdio::null())
+                    .spawn()
+                    .unwrap()
+            })
+            .collect();
+
+        // Each producer's frames must arrive complete and in the order it sent them
+        let mut next = vec![0u32; PRODUCERS as usize];
+        let mut received = 0;
+        while received < PRODUCERS * COUNT {
+            match ring.pop().unwrap() {
+                Some((id, payload)) => {
+                    let n = u32::from_le_bytes(payload.try_into().unwrap());
+                    assert_eq!(n, next[id as usize]);
+                    next[id as usize] += 1;
+                    received += 1;
+                }
+                None => std::thread::yield_now(),
+            }
+        }
+
+        for mut child in children {
+            assert!(child.wait().unwrap().success());
+        }
+        assert_eq!(ring.pop().unwrap(), None);
+
+        unsafe { libc::munmap(ptr as *mut libc::c_void, size) };
+        std::fs::remove_file(path).unwrap();
+    }
+}
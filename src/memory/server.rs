@@ -1,54 +1,175 @@
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
+use std::future::{poll_fn, Future};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
 use std::{io, ptr};
-use std::sync::{Arc, Condvar, Mutex};
+#[cfg(unix)]
+use std::sync::Arc;
+use tokio::task::JoinHandle;
 
 #[cfg(windows)]
 use windows::Win32::System::Memory::{
-    CreateFileMappingA, MapViewOfFile, FILE_MAP_ALL_ACCESS,
+    CreateFileMappingA, MapViewOfFile, UnmapViewOfFile, FILE_MAP_ALL_ACCESS,
     PAGE_READWRITE,
 };
 #[cfg(windows)]
-use windows::Win32::Foundation::{HANDLE, INVALID_HANDLE_VALUE};
+use windows::Win32::Foundation::{CloseHandle, GetLastError, ERROR_ALREADY_EXISTS, HANDLE, INVALID_HANDLE_VALUE};
 #[cfg(windows)]
 use windows::core::PCSTR;
 
 #[cfg(unix)]
-use std::fs::OpenOptions;
+use std::fs::{OpenOptions, Permissions};
+#[cfg(unix)]
+use std::io::{Read, Write};
 #[cfg(unix)]
-use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::fs::{FileExt, MetadataExt, OpenOptionsExt, PermissionsExt};
 #[cfg(unix)]
 use std::os::unix::io::AsRawFd;
 #[cfg(unix)]
 use libc;
+#[cfg(unix)]
+use crate::memory::notify::NamedEvent;
+use crate::memory::ring::{RingFrame, RingHeader, SharedRing, DEFAULT_SLOT_SIZE, RING_HEADER_PREFIX, RING_MAGIC};
 
 #[cfg(windows)]
-use windows::Win32::System::Threading::{CreateEventA, WaitForSingleObject, INFINITE};
+use windows::Win32::System::Threading::{CreateEventA, CreateMutexA, ResetEvent, SetEvent, WaitForSingleObject, INFINITE};
+
+// How the server claims and creates its segment
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct SegmentOptions {
+    // Permission bits for the segment file, written as an octal string in config ("660").
+    // Ignored on Windows.
+    #[serde(deserialize_with = "octal_mode")]
+    pub mode: u32,
+    // Refuse to start if a segment with this name already exists instead of resetting it
+    pub exclusive_create: bool,
+    // Remove the segment and its event when the server is dropped
+    pub unlink_on_exit: bool,
+}
+
+impl Default for SegmentOptions {
+    fn default() -> Self {
+        SegmentOptions {
+            mode: 0o666,
+            exclusive_create: false,
+            unlink_on_exit: true,
+        }
+    }
+}
+
+fn octal_mode<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    let mode = String::deserialize(deserializer)?;
+    u32::from_str_radix(mode.trim_start_matches("0o"), 8)
+        .ok()
+        .filter(|mode| *mode <= 0o777)
+        .ok_or_else(|| D::Error::custom(format!("invalid file mode {:?}", mode)))
+}
+
+// Refuses to reset a segment unless it holds a ring of this version. The owner lock already keeps
+// two servers apart; this guards against a foreign file under the same name or a ring another
+// build still uses.
+fn check_stale_segment(segment: &str, header: Option<RingHeader>) -> io::Result<()> {
+    let header = match header {
+        Some(header) if header.is_ours() => header,
+        Some(RingHeader { magic: RING_MAGIC, version, .. }) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("segment {} holds a version {} ring; refusing to reset it", segment, version),
+            ));
+        }
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("segment {} holds data that isn't a ring; refusing to reset it", segment),
+            ));
+        }
+    };
+
+    // We hold the owner lock, so the recorded owner is gone; a live process with its PID is most
+    // likely a recycled one. The owner mutex is tied to the owning process on Windows.
+    #[cfg(unix)]
+    {
+        let pid = header.owner_pid;
+        let alive = pid != 0
+            && pid != std::process::id()
+            && (unsafe { libc::kill(pid as libc::pid_t, 0) } == 0
+                || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM));
+        if alive {
+            eprintln!("Warning: segment {} was owned by pid {}, which is running again (likely recycled); resetting it", segment, pid);
+        }
+    }
+    #[cfg(windows)]
+    let _ = header;
+    Ok(())
+}
 
 pub struct SharedMemoryServer {
     #[cfg(unix)]
+    #[allow(dead_code)]
     shm_fd: std::fs::File,
+    #[cfg(unix)]
+    lock: SegmentLock,
     #[cfg(windows)]
     mapping_handle: HANDLE,
     #[cfg(windows)]
     event_handle: HANDLE,
+    #[cfg(windows)]
+    owner_handle: HANDLE,
+    #[cfg(unix)]
+    event: Arc<NamedEvent>,
+    #[cfg(unix)]
+    name: String,
+    #[cfg(unix)]
+    unlink_on_exit: bool,
     buffer_size: usize,
     ptr: *mut u8,
-    data_ready: Arc<(Mutex<bool>, Condvar)>,
+    ring: SharedRing,
+    // Blocking wait started by `wait_async`, kept across cancellation so it is never doubled up
+    pending_wait: Option<JoinHandle<io::Result<()>>>,
 }
 
 impl SharedMemoryServer {
     pub fn new(name: &str, size: usize) -> io::Result<Self> {
-        let data_ready = Arc::new((Mutex::new(false), Condvar::new()));
+        Self::with_options(name, size, &SegmentOptions::default())
+    }
 
+    pub fn with_options(name: &str, size: usize, options: &SegmentOptions) -> io::Result<Self> {
         #[cfg(unix)]
         {
+            // Only one server may own a name; a crashed owner's lock goes away with its process
+            let lock = SegmentLock::acquire(name)?;
+
             // Create shared memory file
             let path = format!("/dev/shm/{}", name);
             let file = OpenOptions::new()
                 .read(true)
                 .write(true)
                 .create(true)
-                .mode(0o666)
-                .open(&path)?;
+                .create_new(options.exclusive_create)
+                .truncate(false)
+                .mode(options.mode)
+                .open(&path)
+                .map_err(|e| match e.kind() {
+                    io::ErrorKind::AlreadyExists => io::Error::new(
+                        e.kind(),
+                        format!("segment {} already exists and exclusive_create is set", path),
+                    ),
+                    _ => e,
+                })?;
+            // The mode given to open is filtered through the umask and skipped for existing files
+            file.set_permissions(Permissions::from_mode(options.mode))?;
+
+            // We hold the lock, so a ring already in the file was left behind by a server that
+            // never shut down cleanly. Truncating first zeroes it along with any stale frames.
+            if file.metadata()?.len() > 0 {
+                let mut prefix = [0u8; RING_HEADER_PREFIX];
+                let header = file.read_exact_at(&mut prefix, 0).ok().and_then(|()| RingHeader::parse(&prefix));
+                check_stale_segment(&path, header)?;
+                eprintln!("Resetting stale ring left in segment {}", path);
+                file.set_len(0)?;
+            }
 
             // Set the size of the shared memory segment
             file.set_len(size as u64)?;
@@ -69,11 +190,32 @@ impl SharedMemoryServer {
                 return Err(io::Error::last_os_error());
             }
 
+            // Named semaphore producers post to, the Unix stand-in for the Windows event. A stale
+            // one may still hold posts for frames that no longer exist.
+            let setup = unsafe { SharedRing::init(ptr, size, DEFAULT_SLOT_SIZE) }
+                .and_then(|ring| {
+                    let event = NamedEvent::create(name)?;
+                    while event.try_wait()? {}
+                    Ok((ring, event))
+                });
+            let (ring, event) = match setup {
+                Ok(setup) => setup,
+                Err(e) => {
+                    unsafe { libc::munmap(ptr as *mut libc::c_void, size) };
+                    return Err(e);
+                }
+            };
+
             Ok(SharedMemoryServer {
                 shm_fd: file,
+                lock,
+                event: Arc::new(event),
+                name: name.to_string(),
+                unlink_on_exit: options.unlink_on_exit,
                 buffer_size: size,
                 ptr,
-                data_ready,
+                ring,
+                pending_wait: None,
             })
         }
 
@@ -83,24 +225,68 @@ impl SharedMemoryServer {
                 // Create unique names for Windows shared memory objects
                 let name_cstr = format!("{}\0", name);
                 let event_name = format!("{}_event\0", name);
+                let owner_name = format!("{}_owner\0", name);
+
+                // Named objects live as long as a handle to them does, so an existing owner mutex
+                // means another server is running
+                let owner_handle = CreateMutexA(None, false, PCSTR(owner_name.as_ptr()))?;
+                if GetLastError() == ERROR_ALREADY_EXISTS {
+                    let _ = CloseHandle(owner_handle);
+                    return Err(io::Error::new(
+                        io::ErrorKind::AddrInUse,
+                        format!("segment {} is owned by another server", name),
+                    ));
+                }
 
                 // Create the file mapping object
-                let mapping_handle = CreateFileMappingA(
+                let mapping_handle = match CreateFileMappingA(
                     INVALID_HANDLE_VALUE,
                     None,
                     PAGE_READWRITE,
                     0,
                     size as u32,
                     PCSTR(name_cstr.as_ptr()),
-                )?;
+                ) {
+                    Ok(handle) => handle,
+                    Err(e) => {
+                        let _ = CloseHandle(owner_handle);
+                        return Err(e.into());
+                    }
+                };
+                // Still open in producers that outlived the previous server
+                let stale = GetLastError() == ERROR_ALREADY_EXISTS;
+                if stale && options.exclusive_create {
+                    let _ = CloseHandle(mapping_handle);
+                    let _ = CloseHandle(owner_handle);
+                    return Err(io::Error::new(
+                        io::ErrorKind::AlreadyExists,
+                        format!("segment {} already exists and exclusive_create is set", name),
+                    ));
+                }
 
                 // Create the event for signaling
-                let event_handle = CreateEventA(
+                let event_handle = match CreateEventA(
                     None,
                     false, // Auto-reset event
                     false, // Initial state non-signaled
                     PCSTR(event_name.as_ptr()),
-                )?;
+                ) {
+                    Ok(handle) => handle,
+                    Err(e) => {
+                        let _ = CloseHandle(mapping_handle);
+                        let _ = CloseHandle(owner_handle);
+                        return Err(e.into());
+                    }
+                };
+                let close_handles = || {
+                    let _ = CloseHandle(event_handle);
+                    let _ = CloseHandle(mapping_handle);
+                    let _ = CloseHandle(owner_handle);
+                };
+                if let Err(e) = ResetEvent(event_handle) {
+                    close_handles();
+                    return Err(e.into());
+                }
 
                 // Map view of the file
                 let ptr = MapViewOfFile(
@@ -112,15 +298,39 @@ impl SharedMemoryServer {
                 );
 
                 if ptr.Value.is_null() {
-                    return Err(io::Error::last_os_error());
+                    let e = io::Error::last_os_error();
+                    close_handles();
+                    return Err(e);
                 }
 
+                if stale {
+                    let prefix = std::slice::from_raw_parts(ptr.Value as *const u8, RING_HEADER_PREFIX.min(size));
+                    if let Err(e) = check_stale_segment(name, RingHeader::parse(prefix)) {
+                        let _ = UnmapViewOfFile(ptr);
+                        close_handles();
+                        return Err(e);
+                    }
+                    eprintln!("Resetting stale segment {}", name);
+                    ptr::write_bytes(ptr.Value as *mut u8, 0, size);
+                }
+
+                let ring = match SharedRing::init(ptr.Value as *mut u8, size, DEFAULT_SLOT_SIZE) {
+                    Ok(ring) => ring,
+                    Err(e) => {
+                        let _ = UnmapViewOfFile(ptr);
+                        close_handles();
+                        return Err(e);
+                    }
+                };
+
                 Ok(SharedMemoryServer {
                     mapping_handle,
                     event_handle,
+                    owner_handle,
                     buffer_size: size,
                     ptr: ptr.Value as *mut u8,
-                    data_ready,
+                    ring,
+                    pending_wait: None,
                 })
             }
         }
@@ -135,38 +345,103 @@ impl SharedMemoryServer {
 
         #[cfg(unix)]
         {
-            let (lock, cvar) = &*self.data_ready;
-            let mut data_ready = lock.lock().unwrap();
-            *data_ready = false;
-            drop(cvar.wait(data_ready).unwrap());
-            true
+            match self.event.wait() {
+                Ok(()) => true,
+                Err(e) => {
+                    eprintln!("Error waiting for data: {}", e);
+                    false
+                }
+            }
         }
     }
 
-    pub fn process_data(&mut self) -> io::Result<(u32, Vec<u8>)> {
-        // Read total size (first 4 bytes)
-        let total_size = unsafe { *(self.ptr as *const u32) };
+    // Async version of `wait_for_data` for use on a Tokio runtime. Neither the named semaphore
+    // nor the Windows event has a pollable fd, so the wait itself runs on the blocking pool.
+    // While a producer's abandoned slot holds up the ring, the wait also ends when that slot can
+    // be skipped, since the producer won't signal again.
+    pub async fn wait_async(&mut self) -> io::Result<()> {
+        match self.ring.stall_deadline() {
+            Some(deadline) => tokio::select! {
+                result = poll_fn(|cx| self.poll_wait(cx)) => result,
+                _ = tokio::time::sleep_until(deadline.into()) => Ok(()),
+            },
+            None => poll_fn(|cx| self.poll_wait(cx)).await,
+        }
+    }
 
-        // Read client ID (next 4 bytes)
-        let client_id = unsafe { *(self.ptr.add(4) as *const u32) };
+    // Polls the blocking wait, starting one if none is in flight. Lets `SharedMemoryMux` wait on
+    // several servers at once.
+    pub(crate) fn poll_wait(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if self.pending_wait.is_none() {
+            self.pending_wait = Some(self.spawn_wait());
+        }
+        let result = ready!(Pin::new(self.pending_wait.as_mut().unwrap()).poll(cx));
+        self.pending_wait = None;
+        Poll::Ready(result.map_err(io::Error::other)?)
+    }
 
-        // Read actual response data (remaining bytes)
-        let data_size = total_size - 4; // Subtract size of client ID
-        let mut data = vec![0u8; data_size as usize];
-        unsafe {
-            ptr::copy_nonoverlapping(
-                self.ptr.add(8), // Skip total_size (4) and client_id (4)
-                data.as_mut_ptr(),
-                data_size as usize
-            );
+    // Waits for and copies out the next frame without blocking the runtime
+    pub async fn recv(&mut self) -> io::Result<(u32, Vec<u8>)> {
+        loop {
+            if let Some(frame) = self.process_data()? {
+                return Ok(frame);
+            }
+            self.wait_async().await?;
+        }
+    }
+
+    fn spawn_wait(&self) -> JoinHandle<io::Result<()>> {
+        #[cfg(unix)]
+        {
+            let event = Arc::clone(&self.event);
+            tokio::task::spawn_blocking(move || event.wait())
         }
 
-        Ok((client_id, data))
+        #[cfg(windows)]
+        {
+            // HANDLE isn't Send; the raw value is all the blocking thread needs
+            let raw = self.event_handle.0 as isize;
+            tokio::task::spawn_blocking(move || unsafe {
+                WaitForSingleObject(HANDLE(raw as *mut _), INFINITE);
+                Ok(())
+            })
+        }
+    }
+
+    // Takes the oldest queued frame; `Ok(None)` once the ring is drained
+    pub fn process_data(&mut self) -> io::Result<Option<(u32, Vec<u8>)>> {
+        self.ring.pop()
+    }
+
+    // Same as `process_data` without the copy: the guard derefs to the payload inside the
+    // mapping and hands the slot back to producers when it is dropped. Only checked against
+    // producers that leave committed slots alone; `process_data` checks its own copy.
+    pub fn next_frame(&mut self) -> io::Result<Option<RingFrame<'_>>> {
+        self.ring.pop_frame()
+    }
+
+    pub fn has_frame(&self) -> bool {
+        self.ring.has_frame()
+    }
+
+    pub fn stall_deadline(&self) -> Option<std::time::Instant> {
+        self.ring.stall_deadline()
     }
 }
 
 impl Drop for SharedMemoryServer {
     fn drop(&mut self) {
+        // A blocking wait still in flight would hold its pool thread, and with it runtime
+        // shutdown, until some producer happened to signal
+        if self.pending_wait.take().is_some() {
+            #[cfg(unix)]
+            let _ = self.event.signal();
+            #[cfg(windows)]
+            unsafe {
+                let _ = SetEvent(self.event_handle);
+            }
+        }
+
         unsafe {
             #[cfg(windows)]
             {
@@ -177,6 +452,8 @@ impl Drop for SharedMemoryServer {
                 );
                 windows::Win32::Foundation::CloseHandle(self.mapping_handle);
                 windows::Win32::Foundation::CloseHandle(self.event_handle);
+                // Windows removes the named objects with their last handle
+                windows::Win32::Foundation::CloseHandle(self.owner_handle);
             }
 
             #[cfg(unix)]
@@ -184,5 +461,71 @@ impl Drop for SharedMemoryServer {
                 libc::munmap(self.ptr as *mut libc::c_void, self.buffer_size);
             }
         }
+
+        // Producers still attached keep their mapping; new ones get NotFound instead of a
+        // segment nobody drains. The lock goes last so a new server can't start in between.
+        #[cfg(unix)]
+        {
+            if self.unlink_on_exit {
+                let _ = std::fs::remove_file(format!("/dev/shm/{}", self.name));
+                let _ = NamedEvent::unlink(&self.name);
+            }
+            self.lock.release();
+        }
+    }
+}
+
+// `/dev/shm/<name>.lock`, flocked by the owning server and holding its PID so a refused second
+// server can say who owns the name
+#[cfg(unix)]
+struct SegmentLock {
+    // Only held for its flock
+    #[allow(dead_code)]
+    file: std::fs::File,
+    path: String,
+}
+
+#[cfg(unix)]
+impl SegmentLock {
+    fn acquire(name: &str) -> io::Result<Self> {
+        let path = format!("/dev/shm/{}.lock", name);
+        loop {
+            let mut file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .mode(0o644)
+                .open(&path)?;
+
+            if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+                let err = io::Error::last_os_error();
+                if err.raw_os_error() != Some(libc::EWOULDBLOCK) {
+                    return Err(err);
+                }
+                let mut owner = String::new();
+                let _ = file.read_to_string(&mut owner);
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("segment {} is owned by pid {}", name, owner.trim()),
+                ));
+            }
+
+            // The previous owner may have removed the file between our open and flock, leaving
+            // us holding a lock nobody else can see
+            let locked = file.metadata()?.ino();
+            if std::fs::metadata(&path).map(|m| m.ino()).ok() != Some(locked) {
+                continue;
+            }
+
+            file.set_len(0)?;
+            writeln!(file, "{}", std::process::id())?;
+            return Ok(SegmentLock { file, path });
+        }
+    }
+
+    // Removes the file while still holding the lock; closing the file drops the lock itself
+    fn release(&self) {
+        let _ = std::fs::remove_file(&self.path);
     }
 }
\ No newline at end of file
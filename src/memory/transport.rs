@@ -0,0 +1,372 @@
+use serde::Deserialize;
+use std::future::Future;
+use std::io;
+#[cfg(unix)]
+use std::os::unix::fs::FileTypeExt;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use tokio::sync::mpsc;
+use crate::memory::capture::ReplaySource;
+use crate::memory::mux::{SegmentConfig, SharedMemoryMux};
+use crate::memory::server::{SegmentOptions, SharedMemoryServer};
+
+// Stream transports carry the same frame the shared memory segment originally did:
+// total size (u32, counting the client id), client id (u32), payload. All little endian.
+pub const STREAM_HEADER_SIZE: usize = 8;
+pub const MAX_STREAM_FRAME: usize = 16 * 1024 * 1024;
+
+const ACCEPT_BACKOFF_MIN: Duration = Duration::from_millis(10);
+const ACCEPT_BACKOFF_MAX: Duration = Duration::from_secs(1);
+
+// Anything the server can pull `(client_id, payload)` frames from. The payload is lent to `f`
+// rather than returned so shared memory can keep serving it straight out of the mapping.
+pub trait FrameSource {
+    fn recv_with<R>(&mut self, f: impl FnOnce(u32, &[u8]) -> R) -> impl Future<Output = io::Result<R>>;
+
+    // When the frame served last was originally received, for sources that replay a recording;
+    // None for live sources
+    fn recorded_at(&self) -> Option<u64> {
+        None
+    }
+}
+
+impl FrameSource for SharedMemoryServer {
+    async fn recv_with<R>(&mut self, f: impl FnOnce(u32, &[u8]) -> R) -> io::Result<R> {
+        loop {
+            if let Some(frame) = self.next_frame()? {
+                return Ok(f(frame.client_id(), &frame));
+            }
+            self.wait_async().await?;
+        }
+    }
+}
+
+pub fn encode_frame(client_id: u32, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(STREAM_HEADER_SIZE + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32 + 4).to_le_bytes());
+    frame.extend_from_slice(&client_id.to_le_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+pub async fn write_frame<W: AsyncWrite + Unpin>(writer: &mut W, client_id: u32, payload: &[u8]) -> io::Result<()> {
+    writer.write_all(&encode_frame(client_id, payload)).await
+}
+
+// `Ok(None)` on a clean end of stream between frames
+pub async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Option<(u32, Vec<u8>)>> {
+    let mut header = [0u8; STREAM_HEADER_SIZE];
+    match reader.read_exact(&mut header[..4]).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    reader.read_exact(&mut header[4..]).await?;
+
+    let total_size = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+    let client_id = u32::from_le_bytes(header[4..].try_into().unwrap());
+    if !(4..=MAX_STREAM_FRAME).contains(&total_size) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("frame size {} outside 4..={}", total_size, MAX_STREAM_FRAME),
+        ));
+    }
+
+    let mut payload = vec![0u8; total_size - 4];
+    reader.read_exact(&mut payload).await?;
+    Ok(Some((client_id, payload)))
+}
+
+// Frames from any number of stream connections, funnelled into one queue. Each connection is
+// read by its own task; one that sends garbage is dropped without affecting the others.
+pub struct StreamFrameSource {
+    frames: mpsc::Receiver<(u32, Vec<u8>)>,
+}
+
+impl StreamFrameSource {
+    pub async fn bind_tcp(address: &str) -> io::Result<Self> {
+        let listener = TcpListener::bind(address).await?;
+        let (sender, frames) = mpsc::channel(1024);
+        tokio::spawn(accept_connections(listener, sender));
+        Ok(Self { frames })
+    }
+
+    #[cfg(unix)]
+    pub async fn bind_unix(path: &str) -> io::Result<Self> {
+        // A socket file left behind by a previous run would make bind fail. It is only stale if
+        // nothing accepts connections on it; anything other than a socket isn't ours to delete.
+        match std::fs::symlink_metadata(path) {
+            Ok(metadata) if metadata.file_type().is_socket() => {
+                match std::os::unix::net::UnixStream::connect(path) {
+                    Ok(_) => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::AddrInUse,
+                            format!("{} is in use by another listener", path),
+                        ));
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => std::fs::remove_file(path)?,
+                    Err(e) => return Err(e),
+                }
+            }
+            Ok(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} exists and is not a socket", path),
+                ));
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        let listener = UnixListener::bind(path)?;
+        let (sender, frames) = mpsc::channel(1024);
+        tokio::spawn(accept_connections(listener, sender));
+        Ok(Self { frames })
+    }
+}
+
+// Listeners `StreamFrameSource` can accept connections from
+trait StreamListener: Send + 'static {
+    type Stream: AsyncRead + Unpin + Send + 'static;
+    const KIND: &'static str;
+
+    fn accept_stream(&self) -> impl Future<Output = io::Result<Self::Stream>> + Send;
+}
+
+impl StreamListener for TcpListener {
+    type Stream = tokio::net::TcpStream;
+    const KIND: &'static str = "TCP";
+
+    async fn accept_stream(&self) -> io::Result<Self::Stream> {
+        let (stream, _) = self.accept().await?;
+        let _ = stream.set_nodelay(true);
+        Ok(stream)
+    }
+}
+
+#[cfg(unix)]
+impl StreamListener for UnixListener {
+    type Stream = tokio::net::UnixStream;
+    const KIND: &'static str = "Unix socket";
+
+    async fn accept_stream(&self) -> io::Result<Self::Stream> {
+        Ok(self.accept().await?.0)
+    }
+}
+
+// Hands each connection to its own reader task until the source is dropped. Accept errors such
+// as running out of file descriptors tend to repeat, so they are retried with a growing delay.
+async fn accept_connections<L: StreamListener>(listener: L, sender: mpsc::Sender<(u32, Vec<u8>)>) {
+    let mut backoff = ACCEPT_BACKOFF_MIN;
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept_stream() => accepted,
+            _ = sender.closed() => return,
+        };
+        match accepted {
+            Ok(stream) => {
+                backoff = ACCEPT_BACKOFF_MIN;
+                tokio::spawn(forward_frames(stream, sender.clone()));
+            }
+            Err(e) => {
+                eprintln!("Error accepting {} connection: {}; retrying in {:?}", L::KIND, e, backoff);
+                tokio::select! {
+                    _ = tokio::time::sleep(backoff) => {}
+                    _ = sender.closed() => return,
+                }
+                backoff = (backoff * 2).min(ACCEPT_BACKOFF_MAX);
+            }
+        }
+    }
+}
+
+async fn forward_frames<S: AsyncRead + Unpin>(mut stream: S, sender: mpsc::Sender<(u32, Vec<u8>)>) {
+    loop {
+        match read_frame(&mut stream).await {
+            Ok(Some(frame)) => {
+                if sender.send(frame).await.is_err() {
+                    return;
+                }
+            }
+            Ok(None) => return,
+            Err(e) => {
+                eprintln!("Dropping stream connection: {}", e);
+                return;
+            }
+        }
+    }
+}
+
+impl FrameSource for StreamFrameSource {
+    async fn recv_with<R>(&mut self, f: impl FnOnce(u32, &[u8]) -> R) -> io::Result<R> {
+        match self.frames.recv().await {
+            Some((client_id, payload)) => Ok(f(client_id, &payload)),
+            None => Err(io::Error::new(io::ErrorKind::BrokenPipe, "listener stopped")),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum TransportConfig {
+    SharedMemory {
+        name: String,
+        size: usize,
+        #[serde(flatten)]
+        segment: SegmentOptions,
+    },
+    // One segment per producer or client group, all drained by this server
+    SharedMemoryMux { segments: Vec<SegmentConfig> },
+    Unix { path: String },
+    Tcp { address: String },
+}
+
+impl Default for TransportConfig {
+    fn default() -> Self {
+        TransportConfig::SharedMemory {
+            name: "h278".to_string(),
+            size: 1024 * 1024,
+            segment: SegmentOptions::default(),
+        }
+    }
+}
+
+// The source picked in config; an enum rather than a boxed trait object keeps `recv_with`
+// generic and allocation free
+pub enum AnyFrameSource {
+    SharedMemory(SharedMemoryServer),
+    SharedMemoryMux(SharedMemoryMux),
+    Stream(StreamFrameSource),
+    // A recorded capture rather than a live transport
+    Replay(ReplaySource),
+}
+
+impl AnyFrameSource {
+    pub async fn open(config: &TransportConfig) -> io::Result<Self> {
+        match config {
+            TransportConfig::SharedMemory { name, size, segment } => {
+                Ok(AnyFrameSource::SharedMemory(SharedMemoryServer::with_options(name, *size, segment)?))
+            }
+            TransportConfig::SharedMemoryMux { segments } => {
+                Ok(AnyFrameSource::SharedMemoryMux(SharedMemoryMux::open(segments)?))
+            }
+            #[cfg(unix)]
+            TransportConfig::Unix { path } => Ok(AnyFrameSource::Stream(StreamFrameSource::bind_unix(path).await?)),
+            #[cfg(not(unix))]
+            TransportConfig::Unix { .. } => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Unix sockets are not available on this platform",
+            )),
+            TransportConfig::Tcp { address } => Ok(AnyFrameSource::Stream(StreamFrameSource::bind_tcp(address).await?)),
+        }
+    }
+}
+
+impl FrameSource for AnyFrameSource {
+    async fn recv_with<R>(&mut self, f: impl FnOnce(u32, &[u8]) -> R) -> io::Result<R> {
+        match self {
+            AnyFrameSource::SharedMemory(server) => server.recv_with(f).await,
+            AnyFrameSource::SharedMemoryMux(mux) => mux.recv_with(f).await,
+            AnyFrameSource::Stream(source) => source.recv_with(f).await,
+            AnyFrameSource::Replay(replay) => replay.recv_with(f).await,
+        }
+    }
+
+    fn recorded_at(&self) -> Option<u64> {
+        match self {
+            AnyFrameSource::Replay(replay) => replay.recorded_at(),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn tcp_source_reads_frames_from_several_connections() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        drop(listener);
+        let mut source = StreamFrameSource::bind_tcp(&address).await.unwrap();
+
+        for client_id in 0..2u32 {
+            let mut stream = tokio::net::TcpStream::connect(&address).await.unwrap();
+            write_frame(&mut stream, client_id, b"payload").await.unwrap();
+            let frame = source.recv_with(|id, data| (id, data.to_vec())).await.unwrap();
+            assert_eq!(frame, (client_id, b"payload".to_vec()));
+        }
+
+        // An oversized header only costs the sender its connection
+        let mut bad = tokio::net::TcpStream::connect(&address).await.unwrap();
+        bad.write_all(&u32::MAX.to_le_bytes()).await.unwrap();
+        bad.write_all(&[0; 4]).await.unwrap();
+        let mut good = tokio::net::TcpStream::connect(&address).await.unwrap();
+        write_frame(&mut good, 9, b"").await.unwrap();
+        assert_eq!(source.recv_with(|id, data| (id, data.len())).await.unwrap(), (9, 0));
+    }
+
+    #[tokio::test]
+    async fn dropping_the_source_closes_the_listener() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        drop(listener);
+        drop(StreamFrameSource::bind_tcp(&address).await.unwrap());
+
+        // The accept task notices on its next poll
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(tokio::net::TcpStream::connect(&address).await.is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn unix_source_only_replaces_stale_sockets() {
+        let dir = std::env::temp_dir().join(format!("h278_transport_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("frames.sock");
+        let path = path.to_str().unwrap();
+
+        std::fs::write(path, b"not a socket").unwrap();
+        let err = StreamFrameSource::bind_unix(path).await.err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(std::fs::read(path).unwrap(), b"not a socket");
+        std::fs::remove_file(path).unwrap();
+
+        drop(std::os::unix::net::UnixListener::bind(path).unwrap());
+        let mut source = StreamFrameSource::bind_unix(path).await.unwrap();
+
+        // The first source is still listening, so its socket isn't stale
+        let err = StreamFrameSource::bind_unix(path).await.err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+
+        let mut stream = tokio::net::UnixStream::connect(path).await.unwrap();
+        write_frame(&mut stream, 4, b"payload").await.unwrap();
+        assert_eq!(source.recv_with(|id, data| (id, data.to_vec())).await.unwrap(), (4, b"payload".to_vec()));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn read_frame_rejects_short_total_size() {
+        let mut bytes: &[u8] = &[3, 0, 0, 0, 1, 0, 0, 0];
+        assert_eq!(read_frame(&mut bytes).await.unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        let mut empty: &[u8] = &[];
+        assert!(read_frame(&mut empty).await.unwrap().is_none());
+    }
+
+    #[test]
+    fn shared_memory_config_takes_segment_options() {
+        let config: TransportConfig = serde_json::from_str(
+            r#"{ "kind": "shared_memory", "name": "h278", "size": 4096, "mode": "660", "exclusive_create": true }"#,
+        ).unwrap();
+        let TransportConfig::SharedMemory { segment, .. } = config else { panic!("{:?}", config) };
+        assert_eq!(segment, SegmentOptions { mode: 0o660, exclusive_create: true, unlink_on_exit: true });
+
+        let bad = r#"{ "kind": "shared_memory", "name": "h278", "size": 4096, "mode": "999" }"#;
+        assert!(serde_json::from_str::<TransportConfig>(bad).is_err());
+    }
+}
@@ -0,0 +1,429 @@
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
+use serenity::all::{ChannelId, CreateEmbed, CreateMessage, Http, HttpError};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+use tokio::time::{sleep, timeout_at, Instant};
+
+// Discord rejects messages longer than this, or with more embeds than this
+const MAX_MESSAGE_LEN: usize = 2000;
+const MAX_EMBEDS: usize = 10;
+
+#[derive(Debug, Clone)]
+pub struct Alert {
+    pub content: String,
+    pub embed: Option<CreateEmbed>,
+}
+
+impl From<String> for Alert {
+    fn from(content: String) -> Self {
+        Self { content, embed: None }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OutgoingMessage {
+    pub content: String,
+    pub embeds: Vec<CreateEmbed>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct AlertQueueConfig {
+    pub capacity: usize,
+    // Messages per second per channel; must be above 0
+    #[serde(deserialize_with = "positive_rate")]
+    pub rate_per_sec: f64,
+    pub burst: u32,
+    #[serde(rename = "coalesce_window_ms", deserialize_with = "millis")]
+    pub coalesce_window: Duration,
+    pub max_batch: usize,
+    pub max_retries: u32,
+    #[serde(rename = "base_backoff_ms", deserialize_with = "millis")]
+    pub base_backoff: Duration,
+    pub dead_letter_path: PathBuf,
+}
+
+impl Default for AlertQueueConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 1024,
+            rate_per_sec: 1.0,
+            burst: 5,
+            coalesce_window: Duration::from_millis(250),
+            max_batch: 20,
+            max_retries: 5,
+            base_backoff: Duration::from_millis(500),
+            dead_letter_path: PathBuf::from("dead_letter.log"),
+        }
+    }
+}
+
+// A zero rate would never refill the bucket, and the wait for the next token would be infinite
+fn positive_rate<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    let rate = f64::deserialize(deserializer)?;
+    if rate.is_finite() && rate > 0.0 {
+        Ok(rate)
+    } else {
+        Err(D::Error::custom(format!("rate_per_sec must be above 0, got {}", rate)))
+    }
+}
+
+fn millis<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    u64::deserialize(deserializer).map(Duration::from_millis)
+}
+
+#[derive(Debug)]
+pub struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    refill_per_sec: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(rate_per_sec: f64, burst: u32) -> Self {
+        Self {
+            capacity: burst.max(1) as f64,
+            tokens: burst.max(1) as f64,
+            refill_per_sec: rate_per_sec,
+            last_refill: Instant::now(),
+        }
+    }
+
+    // Takes a token if one is available, otherwise returns how long to wait for the next one
+    pub fn try_take(&mut self, now: Instant) -> Result<(), Duration> {
+        let elapsed = now.saturating_duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            let missing = 1.0 - self.tokens;
+            Err(Duration::from_secs_f64(missing / self.refill_per_sec))
+        }
+    }
+
+    pub async fn acquire(&mut self) {
+        while let Err(wait) = self.try_take(Instant::now()) {
+            sleep(wait).await;
+        }
+    }
+}
+
+// Cuts content longer than `max_len` into pieces that fit, at a line break where there is one.
+// The embed goes with the last piece.
+fn split_oversized(alert: Alert, max_len: usize) -> Vec<Alert> {
+    if alert.content.len() <= max_len {
+        return vec![alert];
+    }
+
+    let mut pieces = Vec::new();
+    let mut rest = alert.content.as_str();
+    while rest.len() > max_len {
+        let mut cut = max_len;
+        while !rest.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        if let Some(newline) = rest[..cut].rfind('\n').filter(|&newline| newline > 0) {
+            cut = newline;
+        } else if cut == 0 {
+            cut = rest.chars().next().map_or(rest.len(), char::len_utf8);
+        }
+        pieces.push(Alert::from(rest[..cut].to_string()));
+        rest = rest[cut..].trim_start_matches('\n');
+    }
+    pieces.push(Alert { content: rest.to_string(), embed: alert.embed });
+    pieces
+}
+
+// Joins queued alerts into as few messages as possible without crossing Discord's limits.
+// Alerts with embeds share identical content lines (e.g. a bare "@everyone"), and one repeating
+// both the content and the embed of an alert already in the message is dropped. Text-only alerts
+// are all kept: the same line can stand for different items.
+pub fn coalesce(alerts: Vec<Alert>, max_len: usize) -> Vec<OutgoingMessage> {
+    let mut messages = Vec::new();
+    let mut current = OutgoingMessage::default();
+    // The alerts that went into `current`
+    let mut included: Vec<Alert> = Vec::new();
+
+    for alert in alerts.into_iter().flat_map(|alert| split_oversized(alert, max_len)) {
+        let shares_line = |current: &OutgoingMessage| {
+            alert.embed.is_some() && current.content.lines().any(|line| line == alert.content)
+        };
+        if alert.embed.is_some() && included.iter().any(|a| a.content == alert.content && a.embed == alert.embed) {
+            continue;
+        }
+        let added_len = if shares_line(&current) || alert.content.is_empty() { 0 } else { alert.content.len() + 1 };
+        let is_empty = current.content.is_empty() && current.embeds.is_empty();
+
+        if !is_empty
+            && (current.content.len() + added_len > max_len
+                || (alert.embed.is_some() && current.embeds.len() == MAX_EMBEDS))
+        {
+            messages.push(std::mem::take(&mut current));
+            included.clear();
+        }
+
+        if !alert.content.is_empty() && !shares_line(&current) {
+            if !current.content.is_empty() {
+                current.content.push('\n');
+            }
+            current.content.push_str(&alert.content);
+        }
+        current.embeds.extend(alert.embed.clone());
+        included.push(alert);
+    }
+
+    if !current.content.is_empty() || !current.embeds.is_empty() {
+        messages.push(current);
+    }
+    messages
+}
+
+#[derive(Debug, Clone)]
+pub struct DeadLetterLog {
+    path: PathBuf,
+}
+
+impl DeadLetterLog {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    pub fn record(&self, channel: ChannelId, reason: &str, message: &OutgoingMessage) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let embeds = serde_json::to_string(&message.embeds).unwrap_or_default();
+        let line = format!(
+            "{}\t{}\t{}\t{}\t{}\n",
+            timestamp,
+            channel,
+            reason,
+            message.content.replace('\n', "\\n"),
+            embeds
+        );
+
+        let written = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(line.as_bytes()));
+
+        if let Err(e) = written {
+            eprintln!("Error writing dead letter log: {}", e);
+        }
+    }
+}
+
+pub struct AlertQueue {
+    channel: ChannelId,
+    sender: mpsc::Sender<Alert>,
+    dead_letter: DeadLetterLog,
+}
+
+impl AlertQueue {
+    pub fn spawn(http: Arc<Http>, channel: ChannelId, config: AlertQueueConfig) -> Self {
+        let (sender, receiver) = mpsc::channel(config.capacity.max(1));
+        let dead_letter = DeadLetterLog::new(config.dead_letter_path.clone());
+
+        tokio::spawn(run_worker(http, channel, config, receiver, dead_letter.clone()));
+
+        Self {
+            channel,
+            sender,
+            dead_letter,
+        }
+    }
+
+    pub fn push(&self, alert: Alert) {
+        if let Err(e) = self.sender.try_send(alert) {
+            let reason = match e {
+                mpsc::error::TrySendError::Full(_) => "queue full",
+                mpsc::error::TrySendError::Closed(_) => "queue closed",
+            };
+            let alert = e.into_inner();
+            let message = OutgoingMessage {
+                content: alert.content,
+                embeds: alert.embed.into_iter().collect(),
+            };
+            self.dead_letter.record(self.channel, reason, &message);
+        }
+    }
+}
+
+async fn run_worker(
+    http: Arc<Http>,
+    channel: ChannelId,
+    config: AlertQueueConfig,
+    mut receiver: mpsc::Receiver<Alert>,
+    dead_letter: DeadLetterLog,
+) {
+    let mut bucket = TokenBucket::new(config.rate_per_sec, config.burst);
+
+    while let Some(first) = receiver.recv().await {
+        // Gather whatever else arrives within the window so a burst goes out as one message
+        let mut batch = vec![first];
+        let deadline = Instant::now() + config.coalesce_window;
+        while batch.len() < config.max_batch {
+            match timeout_at(deadline, receiver.recv()).await {
+                Ok(Some(alert)) => batch.push(alert),
+                _ => break,
+            }
+        }
+
+        for message in coalesce(batch, MAX_MESSAGE_LEN) {
+            bucket.acquire().await;
+            deliver(&http, channel, &config, &dead_letter, message).await;
+        }
+    }
+}
+
+async fn deliver(
+    http: &Http,
+    channel: ChannelId,
+    config: &AlertQueueConfig,
+    dead_letter: &DeadLetterLog,
+    message: OutgoingMessage,
+) {
+    let mut backoff = config.base_backoff;
+
+    for attempt in 0..=config.max_retries {
+        let mut builder = CreateMessage::new().embeds(message.embeds.clone());
+        if !message.content.is_empty() {
+            builder = builder.content(&message.content);
+        }
+
+        match channel.send_message(http, builder).await {
+            Ok(_) => return,
+            Err(why) if !is_transient(&why) => {
+                eprintln!("Discord rejected message: {:?}", why);
+                dead_letter.record(channel, &why.to_string(), &message);
+                return;
+            }
+            Err(why) if attempt < config.max_retries => {
+                eprintln!("Error sending Discord message (attempt {}): {:?}", attempt + 1, why);
+                sleep(backoff).await;
+                backoff *= 2;
+            }
+            Err(why) => {
+                eprintln!("Giving up on Discord message: {:?}", why);
+                dead_letter.record(channel, &why.to_string(), &message);
+            }
+        }
+    }
+}
+
+// Only rate limits, server errors and failed connections can succeed on a retry. Anything else
+// (a bad channel, missing permissions, an invalid embed) would fail the same way every time.
+fn is_transient(error: &serenity::Error) -> bool {
+    match error {
+        serenity::Error::Http(HttpError::UnsuccessfulRequest(response)) => {
+            is_transient_status(response.status_code.as_u16())
+        }
+        serenity::Error::Http(HttpError::Request(_)) | serenity::Error::Io(_) => true,
+        _ => false,
+    }
+}
+
+fn is_transient_status(status: u16) -> bool {
+    status == 429 || (500..600).contains(&status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn coalesce_respects_length_limit() {
+        let alerts = vec!["a".repeat(6).into(), "b".repeat(6).into(), "c".repeat(6).into()];
+        let contents: Vec<String> = coalesce(alerts, 13).into_iter().map(|m| m.content).collect();
+        assert_eq!(contents, vec![format!("{}\n{}", "a".repeat(6), "b".repeat(6)), "c".repeat(6)]);
+    }
+
+    #[test]
+    fn coalesce_splits_oversized_alerts() {
+        let long = format!("{}\n{}", "a".repeat(8), "b".repeat(12));
+        let alerts = vec![Alert { content: long, embed: Some(CreateEmbed::new().title("item")) }];
+        let messages = coalesce(alerts, 10);
+        let contents: Vec<&str> = messages.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(contents, vec!["a".repeat(8), "b".repeat(10), "b".repeat(2)]);
+        assert!(messages.iter().all(|m| m.content.len() <= 10));
+        assert_eq!(messages.iter().map(|m| m.embeds.len()).collect::<Vec<_>>(), vec![0, 0, 1]);
+
+        let wide = coalesce(vec!["éééèèè".to_string().into()], 5);
+        assert_eq!(wide.iter().map(|m| m.content.as_str()).collect::<Vec<_>>(), vec!["éé", "éè", "èè"]);
+    }
+
+    #[test]
+    fn coalesce_groups_embeds_under_shared_content() {
+        let alerts: Vec<Alert> = (0..12)
+            .map(|i| Alert {
+                content: "@everyone".to_string(),
+                embed: Some(CreateEmbed::new().title(i.to_string())),
+            })
+            .collect();
+        let messages = coalesce(alerts, MAX_MESSAGE_LEN);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].content, "@everyone");
+        assert_eq!(messages[0].embeds.len(), MAX_EMBEDS);
+        assert_eq!(messages[1].embeds.len(), 2);
+    }
+
+    #[test]
+    fn coalesce_only_drops_repeats_of_the_same_alert() {
+        let embed = |title: &str| Some(CreateEmbed::new().title(title));
+        let alerts = vec![
+            "Back in stock".to_string().into(),
+            "Back in stock".to_string().into(),
+            Alert { content: "@everyone".to_string(), embed: embed("1") },
+            Alert { content: "@everyone".to_string(), embed: embed("1") },
+            Alert { content: "@everyone".to_string(), embed: embed("2") },
+        ];
+        let messages = coalesce(alerts, MAX_MESSAGE_LEN);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].content, "Back in stock\nBack in stock\n@everyone");
+        assert_eq!(messages[0].embeds.len(), 2);
+    }
+
+    #[test]
+    fn config_rejects_rates_that_never_refill() {
+        let config: AlertQueueConfig =
+            serde_json::from_str(r#"{ "rate_per_sec": 0.5, "coalesce_window_ms": 100 }"#).unwrap();
+        assert_eq!((config.rate_per_sec, config.coalesce_window), (0.5, Duration::from_millis(100)));
+
+        for rate in ["0", "0.0", "-1"] {
+            let json = format!(r#"{{ "rate_per_sec": {} }}"#, rate);
+            assert!(serde_json::from_str::<AlertQueueConfig>(&json).is_err(), "{}", rate);
+        }
+    }
+
+    #[test]
+    fn only_transient_failures_are_retried() {
+        assert!([429, 500, 502, 503].into_iter().all(is_transient_status));
+        assert!(![400, 401, 403, 404, 413].into_iter().any(is_transient_status));
+
+        let network = std::io::Error::new(std::io::ErrorKind::ConnectionReset, "reset");
+        assert!(is_transient(&serenity::Error::Io(network)));
+        assert!(!is_transient(&serenity::Error::Other("invalid embed")));
+    }
+
+    #[tokio::test]
+    async fn token_bucket_limits_bursts() {
+        let mut bucket = TokenBucket::new(2.0, 2);
+        let now = Instant::now();
+        assert!(bucket.try_take(now).is_ok());
+        assert!(bucket.try_take(now).is_ok());
+        let wait = bucket.try_take(now).unwrap_err();
+        assert!(wait <= Duration::from_millis(500));
+        assert!(bucket.try_take(now + Duration::from_millis(500)).is_ok());
+    }
+}
@@ -0,0 +1,226 @@
+use serde::{Deserialize, Deserializer};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::fs;
+use crate::data::cache::CacheError;
+
+// Where the server keeps cooldowns between runs unless config says otherwise
+pub const DEFAULT_STATE_PATH: &str = "dedup.bin";
+
+// Cooldowns are written in whole seconds in config, keyed by client or item id
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct DedupConfig {
+    #[serde(rename = "global_cooldown_secs", deserialize_with = "secs")]
+    pub global_cooldown: Duration,
+    #[serde(rename = "client_cooldowns_secs", deserialize_with = "secs_by_id")]
+    pub client_cooldowns: HashMap<u32, Duration>,
+    #[serde(rename = "item_cooldowns_secs", deserialize_with = "secs_by_id")]
+    pub item_cooldowns: HashMap<u32, Duration>,
+    // None keeps the state in memory only, as replays and tests do
+    pub state_path: Option<PathBuf>,
+    // How often changed state is written; it is also written when the deduplicator is dropped
+    #[serde(rename = "save_interval_secs", deserialize_with = "secs")]
+    pub save_interval: Duration,
+}
+
+impl Default for DedupConfig {
+    fn default() -> Self {
+        Self {
+            global_cooldown: Duration::from_secs(300),
+            client_cooldowns: HashMap::new(),
+            item_cooldowns: HashMap::new(),
+            state_path: Some(DEFAULT_STATE_PATH.into()),
+            save_interval: Duration::from_secs(10),
+        }
+    }
+}
+
+fn secs<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    u64::deserialize(deserializer).map(Duration::from_secs)
+}
+
+fn secs_by_id<'de, D: Deserializer<'de>>(deserializer: D) -> Result<HashMap<u32, Duration>, D::Error> {
+    let secs = HashMap::<u32, u64>::deserialize(deserializer)?;
+    Ok(secs.into_iter().map(|(id, secs)| (id, Duration::from_secs(secs))).collect())
+}
+
+pub struct AlertDeduplicator {
+    config: DedupConfig,
+    // (client_id, item_id) -> unix millis of the last alert sent
+    last_sent: HashMap<(u32, u32), u64>,
+    dirty: bool,
+    last_saved: Instant,
+}
+
+impl AlertDeduplicator {
+    pub fn new(config: DedupConfig) -> Self {
+        Self {
+            config,
+            last_sent: HashMap::new(),
+            dirty: false,
+            last_saved: Instant::now(),
+        }
+    }
+
+    // Restores the state written by a previous run; a missing file just starts empty
+    pub fn load(config: DedupConfig) -> Result<Self, CacheError> {
+        let mut dedup = Self::new(config);
+
+        if let Some(path) = &dedup.config.state_path {
+            match fs::read(path) {
+                Ok(data) => dedup.last_sent = bincode::deserialize(&data)?,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(dedup)
+    }
+
+    // Item overrides win over client overrides, which win over the global cooldown
+    pub fn cooldown_for(&self, client_id: u32, item_id: u32) -> Duration {
+        self.config.item_cooldowns.get(&item_id)
+            .or_else(|| self.config.client_cooldowns.get(&client_id))
+            .copied()
+            .unwrap_or(self.config.global_cooldown)
+    }
+
+    pub fn should_send(&mut self, client_id: u32, item_id: u32) -> bool {
+        self.should_send_at(client_id, item_id, unix_millis(SystemTime::now()))
+    }
+
+    pub fn should_send_at(&mut self, client_id: u32, item_id: u32, now_ms: u64) -> bool {
+        let cooldown = self.cooldown_for(client_id, item_id).as_millis() as u64;
+
+        if let Some(&last) = self.last_sent.get(&(client_id, item_id)) {
+            if now_ms.saturating_sub(last) < cooldown {
+                return false;
+            }
+        }
+
+        self.last_sent.insert((client_id, item_id), now_ms);
+        self.dirty = true;
+        true
+    }
+
+    // Saves once `save_interval` has passed since the last save, so a busy stream doesn't write
+    // the state file for every frame
+    pub fn save_if_due(&mut self) -> Result<(), CacheError> {
+        if self.last_saved.elapsed() < self.config.save_interval {
+            return Ok(());
+        }
+        self.save()
+    }
+
+    pub fn save(&mut self) -> Result<(), CacheError> {
+        if !self.dirty {
+            return Ok(());
+        }
+        self.last_saved = Instant::now();
+
+        // Drop entries whose cooldown has long passed so the state file doesn't grow forever
+        let now_ms = unix_millis(SystemTime::now());
+        let expired: Vec<(u32, u32)> = self.last_sent.iter()
+            .filter(|(&(client_id, item_id), &last)| {
+                now_ms.saturating_sub(last) >= self.cooldown_for(client_id, item_id).as_millis() as u64
+            })
+            .map(|(&key, _)| key)
+            .collect();
+        for key in expired {
+            self.last_sent.remove(&key);
+        }
+
+        if let Some(path) = &self.config.state_path {
+            let tmp_path = path.with_extension("tmp");
+            fs::write(&tmp_path, bincode::serialize(&self.last_sent)?)?;
+            fs::rename(&tmp_path, path)?;
+        }
+
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+impl Drop for AlertDeduplicator {
+    fn drop(&mut self) {
+        if self.config.state_path.is_some() {
+            if let Err(e) = self.save() {
+                eprintln!("Failed to save dedup state: {}", e);
+            }
+        }
+    }
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).unwrap().as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cooldown_blocks_repeats_until_it_expires() {
+        let mut config = DedupConfig {
+            global_cooldown: Duration::from_secs(10),
+            state_path: None,
+            ..Default::default()
+        };
+        config.client_cooldowns.insert(2, Duration::from_secs(1));
+        config.item_cooldowns.insert(42, Duration::from_secs(60));
+        let mut dedup = AlertDeduplicator::new(config);
+
+        assert!(dedup.should_send_at(1, 7, 0));
+        assert!(!dedup.should_send_at(1, 7, 9_999));
+        assert!(dedup.should_send_at(1, 7, 10_000));
+
+        assert!(dedup.should_send_at(2, 7, 0));
+        assert!(dedup.should_send_at(2, 7, 1_000));
+
+        assert!(dedup.should_send_at(2, 42, 0));
+        assert!(!dedup.should_send_at(2, 42, 59_999));
+    }
+
+    #[test]
+    fn state_survives_reload() {
+        let path = std::env::temp_dir().join(format!("dedup-test-{}.bin", std::process::id()));
+        let config = DedupConfig {
+            state_path: Some(path.clone()),
+            ..Default::default()
+        };
+
+        let mut dedup = AlertDeduplicator::load(config.clone()).unwrap();
+        assert!(dedup.should_send(1, 7));
+        dedup.save().unwrap();
+
+        let mut reloaded = AlertDeduplicator::load(config.clone()).unwrap();
+        assert!(!reloaded.should_send(1, 7));
+
+        // Changes within the save interval are written when the deduplicator goes away
+        assert!(reloaded.should_send(1, 8));
+        let saved = fs::read(&path).unwrap();
+        reloaded.save_if_due().unwrap();
+        assert_eq!(fs::read(&path).unwrap(), saved);
+        drop(reloaded);
+        assert!(!AlertDeduplicator::load(config).unwrap().should_send(1, 8));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn cooldowns_come_from_config_in_seconds() {
+        let config: DedupConfig = serde_json::from_str(
+            r#"{ "global_cooldown_secs": 60, "client_cooldowns_secs": { "2": 5 }, "item_cooldowns_secs": { "42": 600 } }"#,
+        ).unwrap();
+        let dedup = AlertDeduplicator::new(config.clone());
+        assert_eq!(dedup.cooldown_for(1, 7), Duration::from_secs(60));
+        assert_eq!(dedup.cooldown_for(2, 7), Duration::from_secs(5));
+        assert_eq!(dedup.cooldown_for(2, 42), Duration::from_secs(600));
+        assert_eq!(config.state_path, Some(PathBuf::from(DEFAULT_STATE_PATH)));
+        assert_eq!(DedupConfig::default().state_path, config.state_path);
+
+        let in_memory: DedupConfig = serde_json::from_str(r#"{ "state_path": null }"#).unwrap();
+        assert_eq!(in_memory.state_path, None);
+    }
+}
@@ -1,2 +1,8 @@
 pub mod reader_service;
-pub mod channel;
\ No newline at end of file
+pub mod channel;
+pub mod alert_queue;
+pub mod dedup;
+pub mod template;
+pub mod rules;
+pub mod simulator;
+pub mod pipeline;
@@ -0,0 +1,213 @@
+use arc_swap::ArcSwap;
+use std::io;
+use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use crate::data::catalog::Catalog;
+use crate::data::history::HistoryStore;
+use crate::huffman::decoder::OptimizedHuffmanDecoder;
+use crate::memory::capture::CaptureWriter;
+use crate::memory::transport::FrameSource;
+use crate::service::alert_queue::Alert;
+use crate::service::channel::DiscordChannels;
+use crate::service::dedup::AlertDeduplicator;
+use crate::service::reader_service::parse_stock_records;
+use crate::service::rules::{RuleEngine, RuleInput, Sink};
+use crate::service::template::{AlertContext, AlertRenderer};
+
+// Where rendered alerts go, once per sink the matching rules name
+pub trait AlertOutput {
+    fn deliver(&mut self, client_id: u32, sink: Sink, alert: Alert);
+}
+
+// The server's output: log alerts go to stdout, Discord alerts to the client's channel. Stubbed,
+// Discord alerts are printed instead, as a replay does without `--live-sinks`.
+pub struct DiscordOutput {
+    channels: DiscordChannels,
+    stubbed: bool,
+}
+
+impl DiscordOutput {
+    pub fn new(channels: DiscordChannels) -> Self {
+        Self { channels, stubbed: false }
+    }
+
+    pub fn with_stub(mut self, stubbed: bool) -> Self {
+        self.stubbed = stubbed;
+        self
+    }
+}
+
+impl AlertOutput for DiscordOutput {
+    fn deliver(&mut self, client_id: u32, sink: Sink, alert: Alert) {
+        match sink {
+            Sink::Log => println!("{}", alert.content),
+            Sink::Discord if self.stubbed => {
+                println!("[replay] Discord alert for client {}: {}", client_id, alert.content)
+            }
+            Sink::Discord => self.channels.send_message(client_id, alert),
+        }
+    }
+}
+
+// What became of one frame
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameReport {
+    pub client_id: u32,
+    pub records: usize,
+    // Records some rule matched, and the ones of those the cooldowns let through
+    pub matched: usize,
+    pub alerts: usize,
+}
+
+// Everything between a frame arriving and its alerts going out: decode, parse, record, evaluate
+// rules, dedup, render and deliver
+pub struct Pipeline<O> {
+    catalog: Arc<ArcSwap<Catalog>>,
+    rules: RuleEngine,
+    renderer: AlertRenderer,
+    dedup: AlertDeduplicator,
+    output: O,
+    decoder: OptimizedHuffmanDecoder,
+    capture: Option<CaptureWriter>,
+    history: Option<HistoryStore>,
+    save_dedup: bool,
+}
+
+impl<O: AlertOutput> Pipeline<O> {
+    // Frames read whichever catalog is current, so a reloader can swap lists underneath
+    pub fn new(
+        catalog: Arc<ArcSwap<Catalog>>,
+        rules: RuleEngine,
+        renderer: AlertRenderer,
+        dedup: AlertDeduplicator,
+        output: O,
+    ) -> Self {
+        Self {
+            catalog,
+            rules,
+            renderer,
+            dedup,
+            output,
+            decoder: OptimizedHuffmanDecoder::new(),
+            capture: None,
+            history: None,
+            save_dedup: false,
+        }
+    }
+
+    pub fn with_capture(mut self, capture: CaptureWriter) -> Self {
+        self.capture = Some(capture);
+        self
+    }
+
+    pub fn with_history(mut self, history: HistoryStore) -> Self {
+        self.history = Some(history);
+        self
+    }
+
+    // Persist the dedup state every `save_interval`. Off for replays: saving prunes by the wall
+    // clock, which would undo a replay's recorded one.
+    pub fn with_dedup_saving(mut self) -> Self {
+        self.save_dedup = true;
+        self
+    }
+
+    pub fn output(&self) -> &O {
+        &self.output
+    }
+
+    pub fn output_mut(&mut self) -> &mut O {
+        &mut self.output
+    }
+
+    // Waits for the next frame and handles it. Malformed frames are `InvalidData`; any other
+    // error comes from the source.
+    pub async fn next_frame<S: FrameSource>(&mut self, source: &mut S) -> io::Result<FrameReport> {
+        // Decode while the frame is still lent out, so shared memory never copies it
+        let (decoder, capture) = (&mut self.decoder, &mut self.capture);
+        let (client_id, start, received_at, decoded) = source.recv_with(|client_id, data| {
+            let start = Instant::now();
+            let received_at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+            if let Some(writer) = capture.as_mut() {
+                if let Err(err) = writer.write(client_id, received_at, data) {
+                    eprintln!("Failed to capture frame: {}", err);
+                }
+            }
+            (client_id, start, received_at, decoder.try_decode_to_bytes(data))
+        }).await?;
+        if let Some(writer) = self.capture.as_mut() {
+            if let Err(err) = writer.flush_if_due() {
+                eprintln!("Failed to flush frame capture: {}", err);
+            }
+        }
+
+        let decoded = decoded.map_err(|err| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("frame from client {}: {}", client_id, err))
+        })?;
+        // Replays run on the recorded clock, so cooldowns play out as they did originally
+        let received_at = source.recorded_at().unwrap_or(received_at);
+        Ok(self.handle_since(start, client_id, received_at, &decoded))
+    }
+
+    // Handles an already decoded payload received at `received_at` (Unix millis)
+    pub fn handle(&mut self, client_id: u32, received_at: u64, decoded: &[u8]) -> FrameReport {
+        self.handle_since(Instant::now(), client_id, received_at, decoded)
+    }
+
+    fn handle_since(&mut self, start: Instant, client_id: u32, received_at: u64, decoded: &[u8]) -> FrameReport {
+        let result = parse_stock_records(decoded);
+        let mut report = FrameReport { client_id, records: result.len(), ..FrameReport::default() };
+        if result.is_empty() {
+            return report;
+        }
+
+        if let Some(store) = self.history.as_mut() {
+            if let Err(err) = store.append(client_id, received_at, &result) {
+                eprintln!("Failed to record stock history: {}", err);
+            }
+        }
+
+        println!("Time taken: {} ns", start.elapsed().as_nanos());
+        let catalog = self.catalog.load();
+
+        for record in &result {
+            let entry = catalog.lookup(record.id);
+
+            let input = RuleInput { client_id, record, lists: &entry };
+            let sinks: Vec<Sink> = self.rules.evaluate(&input).map(|rule| rule.sink).collect();
+            if sinks.is_empty() {
+                continue;
+            }
+            report.matched += 1;
+            if !self.dedup.should_send_at(client_id, record.id, received_at) {
+                continue;
+            }
+            report.alerts += 1;
+
+            let alert = self.renderer.render(&AlertContext {
+                client_id,
+                item_name: entry.name().unwrap_or("Unknown"),
+                item_id: record.id,
+                stock: record.stock,
+                price: record.price,
+                timestamp: record.timestamp,
+                thumbnail_url: entry.record().and_then(|item| item.image_url()),
+            });
+
+            // Several rules may name the same sink; each sink gets the alert once
+            for sink in [Sink::Log, Sink::Discord] {
+                if sinks.contains(&sink) {
+                    self.output.deliver(client_id, sink, alert.clone());
+                }
+            }
+            println!("COMPLETE OUTFIT NOTIF TIME TAKEN: {} ns", start.elapsed().as_nanos());
+        }
+
+        if self.save_dedup {
+            if let Err(e) = self.dedup.save_if_due() {
+                eprintln!("Failed to save dedup state: {}", e);
+            }
+        }
+        report
+    }
+}
@@ -35,3 +35,58 @@ pub fn parse_stock_bytes(input: &[u8]) -> Vec<(u32, u32)> {
     }
     result
 }
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StockRecord {
+    pub id: u32,
+    pub stock: u32,
+    pub price: u64,
+    pub timestamp: u64,
+}
+
+// Same wire format as `parse_stock_bytes` (`id-stock-price-timestamp|`) but keeps every field
+// and every record, including the ones with no stock. A record with a field too large for its
+// type is skipped rather than wrapped into some other item.
+pub fn parse_stock_records(input: &[u8]) -> Vec<StockRecord> {
+    input
+        .split(|&b| b == b'|')
+        .filter(|record| !record.is_empty())
+        .filter_map(|record| {
+            let mut fields = record.split(|&b| b == b'-').map(parse_digits);
+            let mut next = || fields.next().unwrap_or(Some(0));
+            Some(StockRecord {
+                id: u32::try_from(next()?).ok()?,
+                stock: u32::try_from(next()?).ok()?,
+                price: next()?,
+                timestamp: next()?,
+            })
+        })
+        .collect()
+}
+
+// None when the digits overflow a u64
+fn parse_digits(field: &[u8]) -> Option<u64> {
+    field.iter()
+        .filter(|b| b.is_ascii_digit())
+        .try_fold(0u64, |acc, &b| acc.checked_mul(10)?.checked_add((b - b'0') as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_with_oversized_fields_are_skipped() {
+        let input = b"20067-3-104-1700000000|4294967296-1-1-1|20069-4294967296-1-1|\
+            20070-1-99999999999999999999-1|20071-1-18446744073709551615-1|";
+        let records = parse_stock_records(input);
+        assert_eq!(
+            records,
+            vec![
+                StockRecord { id: 20067, stock: 3, price: 104, timestamp: 1_700_000_000 },
+                StockRecord { id: 20071, stock: 1, price: u64::MAX, timestamp: 1 },
+            ]
+        );
+    }
+}
@@ -0,0 +1,279 @@
+use serde::Deserialize;
+use std::fmt;
+use crate::data::catalog::CatalogEntry;
+use crate::service::reader_service::StockRecord;
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Condition {
+    All(Vec<Condition>),
+    Any(Vec<Condition>),
+    Not(Box<Condition>),
+    InList(String),
+    StockAtLeast(u32),
+    StockAtMost(u32),
+    PriceAtLeast(u64),
+    PriceAtMost(u64),
+    // Against the item's own `min_stock`/`max_price` from the catalog. Items without the
+    // threshold don't match.
+    StockAtLeastItemMin,
+    PriceAtMostItemMax,
+    ClientIn(Vec<u32>),
+    IdRange { min: u32, max: u32 },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuleError {
+    // An id range whose min is above its max matches nothing
+    InvalidIdRange { rule: String, min: u32, max: u32 },
+}
+
+impl fmt::Display for RuleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuleError::InvalidIdRange { rule, min, max } => {
+                write!(f, "rule '{}': id_range min {} is above max {}", rule, min, max)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RuleError {}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Sink {
+    // The Discord channel assigned to the frame's client
+    Discord,
+    Log,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub name: String,
+    pub sink: Sink,
+    pub when: Condition,
+}
+
+// Which item lists contain the record's id, and the item's own thresholds. A catalog entry
+// answers by looking the id up, so nothing is collected per record.
+pub trait ListMembership {
+    fn in_list(&self, list: &str) -> bool;
+
+    fn item_min_stock(&self) -> Option<u32> {
+        None
+    }
+
+    fn item_max_price(&self) -> Option<u64> {
+        None
+    }
+}
+
+impl ListMembership for CatalogEntry<'_> {
+    fn in_list(&self, list: &str) -> bool {
+        CatalogEntry::in_list(self, list)
+    }
+
+    fn item_min_stock(&self) -> Option<u32> {
+        self.record().and_then(|record| record.min_stock())
+    }
+
+    fn item_max_price(&self) -> Option<u64> {
+        self.record().and_then(|record| record.max_price())
+    }
+}
+
+impl ListMembership for [&str] {
+    fn in_list(&self, list: &str) -> bool {
+        self.contains(&list)
+    }
+}
+
+impl<const N: usize> ListMembership for [&str; N] {
+    fn in_list(&self, list: &str) -> bool {
+        self.contains(&list)
+    }
+}
+
+// Everything a rule can look at for one record
+pub struct RuleInput<'a, L: ListMembership + ?Sized = [&'a str]> {
+    pub client_id: u32,
+    pub record: &'a StockRecord,
+    pub lists: &'a L,
+}
+
+impl Condition {
+    pub fn matches<L: ListMembership + ?Sized>(&self, input: &RuleInput<L>) -> bool {
+        match self {
+            Condition::All(conditions) => conditions.iter().all(|c| c.matches(input)),
+            Condition::Any(conditions) => conditions.iter().any(|c| c.matches(input)),
+            Condition::Not(condition) => !condition.matches(input),
+            Condition::InList(name) => input.lists.in_list(name),
+            Condition::StockAtLeast(min) => input.record.stock >= *min,
+            Condition::StockAtMost(max) => input.record.stock <= *max,
+            Condition::PriceAtLeast(min) => input.record.price >= *min,
+            Condition::PriceAtMost(max) => input.record.price <= *max,
+            Condition::StockAtLeastItemMin => input.lists.item_min_stock().is_some_and(|min| input.record.stock >= min),
+            Condition::PriceAtMostItemMax => input.lists.item_max_price().is_some_and(|max| input.record.price <= max),
+            Condition::ClientIn(clients) => clients.contains(&input.client_id),
+            Condition::IdRange { min, max } => (*min..=*max).contains(&input.record.id),
+        }
+    }
+
+    pub fn referenced_lists<'a>(&'a self, out: &mut Vec<&'a str>) {
+        match self {
+            Condition::All(conditions) | Condition::Any(conditions) => {
+                conditions.iter().for_each(|c| c.referenced_lists(out))
+            }
+            Condition::Not(condition) => condition.referenced_lists(out),
+            Condition::InList(name) => out.push(name),
+            _ => {}
+        }
+    }
+
+    fn validate(&self, rule: &str) -> Result<(), RuleError> {
+        match self {
+            Condition::All(conditions) | Condition::Any(conditions) => {
+                conditions.iter().try_for_each(|c| c.validate(rule))
+            }
+            Condition::Not(condition) => condition.validate(rule),
+            Condition::IdRange { min, max } if min > max => {
+                Err(RuleError::InvalidIdRange { rule: rule.to_string(), min: *min, max: *max })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+// The rule the server shipped with: in `all`, not in `bloody`, and actually in stock
+pub fn default_rules() -> Vec<Rule> {
+    vec![Rule {
+        name: "restock".to_string(),
+        sink: Sink::Discord,
+        when: Condition::All(vec![
+            Condition::InList("all".to_string()),
+            Condition::Not(Box::new(Condition::InList("bloody".to_string()))),
+            Condition::StockAtLeast(1),
+        ]),
+    }]
+}
+
+pub struct RuleEngine {
+    rules: Vec<Rule>,
+}
+
+impl RuleEngine {
+    pub fn new(rules: Vec<Rule>) -> Result<Self, RuleError> {
+        for rule in &rules {
+            rule.when.validate(&rule.name)?;
+        }
+        Ok(Self { rules })
+    }
+
+    // (rule name, list name) for every list a rule mentions
+    pub fn referenced_lists(&self) -> Vec<(&str, &str)> {
+        let mut out = Vec::new();
+        for rule in &self.rules {
+            let mut lists = Vec::new();
+            rule.when.referenced_lists(&mut lists);
+            out.extend(lists.into_iter().map(|list| (rule.name.as_str(), list)));
+        }
+        out
+    }
+
+    pub fn evaluate<'a, L: ListMembership + ?Sized>(
+        &'a self,
+        input: &'a RuleInput<'a, L>,
+    ) -> impl Iterator<Item = &'a Rule> + 'a {
+        self.rules.iter().filter(move |rule| rule.when.matches(input))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::catalog::Catalog;
+    use crate::data::format::{ItemList, ItemRecord};
+
+    fn record(id: u32, stock: u32, price: u64) -> StockRecord {
+        StockRecord { id, stock, price, timestamp: 0 }
+    }
+
+    #[test]
+    fn default_rule_matches_legacy_filter() {
+        let engine = RuleEngine::new(default_rules()).unwrap();
+        let in_stock = record(1, 3, 10);
+        let sold_out = record(1, 0, 10);
+
+        let matches = |record: &StockRecord, lists: &[&str]| {
+            let input = RuleInput { client_id: 0, record, lists };
+            engine.evaluate(&input).count()
+        };
+
+        assert_eq!(matches(&in_stock, &["all"]), 1);
+        assert_eq!(matches(&in_stock, &["all", "bloody"]), 0);
+        assert_eq!(matches(&in_stock, &[]), 0);
+        assert_eq!(matches(&sold_out, &["all"]), 0);
+    }
+
+    #[test]
+    fn parses_rules_from_json() {
+        let json = r#"[{
+            "name": "cheap watchlist",
+            "sink": "log",
+            "when": { "all": [
+                { "in_list": "watchlist" },
+                { "price_at_most": 500 },
+                { "client_in": [1, 2] },
+                { "id_range": { "min": 20000, "max": 29999 } }
+            ]}
+        }]"#;
+        let rules: Vec<Rule> = serde_json::from_str(json).unwrap();
+        let engine = RuleEngine::new(rules).unwrap();
+
+        let cheap = record(20067, 1, 104);
+        let input = RuleInput { client_id: 2, record: &cheap, lists: &["watchlist"] };
+        assert_eq!(engine.evaluate(&input).map(|r| r.sink).collect::<Vec<_>>(), vec![Sink::Log]);
+
+        let input = RuleInput { client_id: 3, record: &cheap, lists: &["watchlist"] };
+        assert_eq!(engine.evaluate(&input).count(), 0);
+    }
+
+    #[test]
+    fn item_thresholds_come_from_the_catalog() {
+        let mut catalog = Catalog::new();
+        catalog.insert("all", ItemList::from_iter([
+            (1, ItemRecord { min_stock: Some(5), max_price: Some(100), ..ItemRecord::named("Sword") }),
+            (2, ItemRecord::named("Shield")),
+        ]));
+        let rules: Vec<Rule> = serde_json::from_str(r#"[{
+            "name": "item thresholds",
+            "sink": "log",
+            "when": { "all": ["stock_at_least_item_min", "price_at_most_item_max"] }
+        }]"#).unwrap();
+        let engine = RuleEngine::new(rules).unwrap();
+
+        let matches = |record: StockRecord| {
+            let entry = catalog.lookup(record.id);
+            let input = RuleInput { client_id: 0, record: &record, lists: &entry };
+            engine.evaluate(&input).count()
+        };
+        assert_eq!(matches(record(1, 5, 100)), 1);
+        assert_eq!(matches(record(1, 4, 100)), 0);
+        assert_eq!(matches(record(1, 5, 101)), 0);
+        // No thresholds set, or not in the catalog at all
+        assert_eq!(matches(record(2, 50, 1)), 0);
+        assert_eq!(matches(record(3, 50, 1)), 0);
+    }
+
+    #[test]
+    fn rejects_inverted_id_range() {
+        let rule = Rule {
+            name: "bad".to_string(),
+            sink: Sink::Log,
+            when: Condition::Not(Box::new(Condition::IdRange { min: 10, max: 1 })),
+        };
+        let err = RuleEngine::new(vec![rule]).err().unwrap();
+        assert_eq!(err, RuleError::InvalidIdRange { rule: "bad".to_string(), min: 10, max: 1 });
+    }
+}
@@ -0,0 +1,124 @@
+use std::fmt::Write;
+
+// Settings for generated stock frames. Every frame is one client's snapshot of the whole item
+// range, the way the upstream producer reports it: most items at 0, a few restocked and selling
+// down until they're gone.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulatorConfig {
+    pub first_id: u32,
+    pub items: u32,
+    pub clients: u32,
+    // Chance per item and frame that an empty item is restocked
+    pub restock_probability: f64,
+    // Chance per item and frame that an item in stock sells one unit
+    pub sell_probability: f64,
+    pub max_restock: u32,
+    pub seed: u64,
+}
+
+impl Default for SimulatorConfig {
+    fn default() -> Self {
+        Self {
+            first_id: 20000,
+            items: 1000,
+            clients: 4,
+            restock_probability: 0.001,
+            sell_probability: 0.2,
+            max_restock: 20,
+            seed: 0x5eed,
+        }
+    }
+}
+
+// Generates `id-stock-price-timestamp|` payloads; the same seed gives the same frames
+pub struct StockSimulator {
+    config: SimulatorConfig,
+    // Stock per client, then item
+    stock: Vec<Vec<u32>>,
+    prices: Vec<u64>,
+    next_client: u32,
+    rng: XorShift,
+}
+
+impl StockSimulator {
+    pub fn new(config: SimulatorConfig) -> Self {
+        let mut rng = XorShift::new(config.seed);
+        let prices = (0..config.items).map(|_| 10 + rng.below(990)).collect();
+        let stock = vec![vec![0; config.items as usize]; config.clients.max(1) as usize];
+        StockSimulator { config, stock, prices, next_client: 0, rng }
+    }
+
+    // The next client's snapshot, clients taking turns. `timestamp` is written into every record.
+    pub fn next_frame(&mut self, timestamp: u64) -> (u32, String) {
+        let client_id = self.next_client;
+        self.next_client = (self.next_client + 1) % self.stock.len() as u32;
+
+        let mut payload = String::with_capacity(self.config.items as usize * 24);
+        for (index, stock) in self.stock[client_id as usize].iter_mut().enumerate() {
+            if *stock == 0 {
+                if self.rng.chance(self.config.restock_probability) {
+                    *stock = 1 + self.rng.below(self.config.max_restock.max(1) as u64) as u32;
+                }
+            } else if self.rng.chance(self.config.sell_probability) {
+                *stock -= 1;
+            }
+            let id = self.config.first_id + index as u32;
+            let _ = write!(payload, "{}-{}-{}-{}|", id, stock, self.prices[index], timestamp);
+        }
+        (client_id, payload)
+    }
+
+    // Items currently in stock across all clients
+    pub fn in_stock(&self) -> usize {
+        self.stock.iter().flatten().filter(|&&stock| stock > 0).count()
+    }
+}
+
+// xorshift64*; plenty for load generation and keeps runs reproducible without a dependency
+struct XorShift(u64);
+
+impl XorShift {
+    fn new(seed: u64) -> Self {
+        XorShift(seed.max(1))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        ((self.next() >> 11) as f64 / (1u64 << 53) as f64) < probability
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::reader_service::parse_stock_records;
+
+    #[test]
+    fn frames_are_reproducible_snapshots() {
+        let config = SimulatorConfig { items: 50, clients: 2, restock_probability: 0.3, ..SimulatorConfig::default() };
+        let mut first = StockSimulator::new(config.clone());
+        let mut second = StockSimulator::new(config);
+
+        for frame in 0..6 {
+            let (client_id, payload) = first.next_frame(1_700_000_000);
+            assert_eq!((client_id, payload.clone()), second.next_frame(1_700_000_000));
+            assert_eq!(client_id, frame % 2);
+
+            let records = parse_stock_records(payload.as_bytes());
+            assert_eq!(records.len(), 50);
+            assert_eq!((records[0].id, records[49].id), (20000, 20049));
+            assert!(records.iter().all(|record| record.timestamp == 1_700_000_000 && record.stock <= 20));
+        }
+        assert!(first.in_stock() > 0);
+    }
+}
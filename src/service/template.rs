@@ -0,0 +1,273 @@
+use serde::Deserialize;
+use serenity::all::{CreateEmbed, Timestamp};
+use std::fmt;
+use crate::service::alert_queue::Alert;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Placeholder {
+    Client,
+    Item,
+    Id,
+    Stock,
+    Price,
+    Timestamp,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Literal(String),
+    Field(Placeholder),
+}
+
+#[derive(Debug)]
+pub enum TemplateError {
+    UnknownPlaceholder(String),
+    Unclosed(usize),
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateError::UnknownPlaceholder(name) => write!(f, "Unknown placeholder: {{{}}}", name),
+            TemplateError::Unclosed(pos) => write!(f, "Unclosed placeholder at byte {}", pos),
+        }
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+pub struct AlertContext<'a> {
+    pub client_id: u32,
+    pub item_name: &'a str,
+    pub item_id: u32,
+    pub stock: u32,
+    pub price: u64,
+    pub timestamp: u64,
+    pub thumbnail_url: Option<&'a str>,
+}
+
+// A template is parsed once at startup so rendering is just a walk over the segments
+#[derive(Debug, Clone, PartialEq)]
+pub struct MessageTemplate {
+    segments: Vec<Segment>,
+}
+
+impl MessageTemplate {
+    pub fn parse(template: &str) -> Result<Self, TemplateError> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut rest = template;
+
+        while let Some(pos) = rest.find(['{', '}']) {
+            literal.push_str(&rest[..pos]);
+            let tail = &rest[pos..];
+
+            // `{{` and `}}` are escaped braces
+            if tail.starts_with("{{") || tail.starts_with("}}") {
+                literal.push_str(&tail[..1]);
+                rest = &tail[2..];
+                continue;
+            }
+            if let Some(after) = tail.strip_prefix('}') {
+                literal.push('}');
+                rest = after;
+                continue;
+            }
+
+            let offset = template.len() - tail.len();
+            let end = tail.find('}').ok_or(TemplateError::Unclosed(offset))?;
+            let placeholder = match &tail[1..end] {
+                "client" => Placeholder::Client,
+                "item" => Placeholder::Item,
+                "id" => Placeholder::Id,
+                "stock" => Placeholder::Stock,
+                "price" => Placeholder::Price,
+                "timestamp" => Placeholder::Timestamp,
+                other => return Err(TemplateError::UnknownPlaceholder(other.to_string())),
+            };
+
+            if !literal.is_empty() {
+                segments.push(Segment::Literal(std::mem::take(&mut literal)));
+            }
+            segments.push(Segment::Field(placeholder));
+            rest = &tail[end + 1..];
+        }
+
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+
+        Ok(Self { segments })
+    }
+
+    pub fn render(&self, ctx: &AlertContext) -> String {
+        let mut out = String::with_capacity(64);
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(text) => out.push_str(text),
+                Segment::Field(Placeholder::Client) => out.push_str(&ctx.client_id.to_string()),
+                Segment::Field(Placeholder::Item) => out.push_str(ctx.item_name),
+                Segment::Field(Placeholder::Id) => out.push_str(&ctx.item_id.to_string()),
+                Segment::Field(Placeholder::Stock) => out.push_str(&ctx.stock.to_string()),
+                Segment::Field(Placeholder::Price) => out.push_str(&ctx.price.to_string()),
+                Segment::Field(Placeholder::Timestamp) => out.push_str(&ctx.timestamp.to_string()),
+            }
+        }
+        out
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct StockColor {
+    pub min_stock: u32,
+    pub color: u32,
+}
+
+fn default_stock_colors() -> Vec<StockColor> {
+    vec![
+        StockColor { min_stock: 0, color: 0xE74C3C },
+        StockColor { min_stock: 5, color: 0xE67E22 },
+        StockColor { min_stock: 20, color: 0x2ECC71 },
+    ]
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct EmbedTemplateConfig {
+    pub title: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    // Used when the item's metadata has no image of its own
+    #[serde(default)]
+    pub thumbnail: Option<String>,
+    #[serde(default = "default_stock_colors")]
+    pub stock_colors: Vec<StockColor>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct AlertTemplateConfig {
+    pub text: String,
+    pub embed: Option<EmbedTemplateConfig>,
+}
+
+impl Default for AlertTemplateConfig {
+    fn default() -> Self {
+        Self {
+            text: "@everyone Client {client}: Item: {item} (ID: {id}) - Stock: {stock}".to_string(),
+            embed: None,
+        }
+    }
+}
+
+struct EmbedRenderer {
+    title: MessageTemplate,
+    description: Option<MessageTemplate>,
+    thumbnail: Option<MessageTemplate>,
+    stock_colors: Vec<StockColor>,
+}
+
+pub struct AlertRenderer {
+    text: MessageTemplate,
+    embed: Option<EmbedRenderer>,
+}
+
+impl AlertRenderer {
+    pub fn new(config: &AlertTemplateConfig) -> Result<Self, TemplateError> {
+        let embed = match &config.embed {
+            Some(embed) => {
+                let mut stock_colors = embed.stock_colors.clone();
+                stock_colors.sort_by_key(|c| c.min_stock);
+                Some(EmbedRenderer {
+                    title: MessageTemplate::parse(&embed.title)?,
+                    description: embed.description.as_deref().map(MessageTemplate::parse).transpose()?,
+                    thumbnail: embed.thumbnail.as_deref().map(MessageTemplate::parse).transpose()?,
+                    stock_colors,
+                })
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            text: MessageTemplate::parse(&config.text)?,
+            embed,
+        })
+    }
+
+    pub fn render(&self, ctx: &AlertContext) -> Alert {
+        Alert {
+            content: self.text.render(ctx),
+            embed: self.embed.as_ref().map(|embed| embed.render(ctx)),
+        }
+    }
+}
+
+impl EmbedRenderer {
+    fn render(&self, ctx: &AlertContext) -> CreateEmbed {
+        let mut embed = CreateEmbed::new()
+            .title(self.title.render(ctx))
+            .field("Client", ctx.client_id.to_string(), true)
+            .field("Item ID", ctx.item_id.to_string(), true)
+            .field("Stock", ctx.stock.to_string(), true)
+            .field("Price", ctx.price.to_string(), true);
+
+        if let Some(description) = &self.description {
+            embed = embed.description(description.render(ctx));
+        }
+
+        let thumbnail = ctx.thumbnail_url
+            .map(str::to_string)
+            .or_else(|| self.thumbnail.as_ref().map(|t| t.render(ctx)));
+        if let Some(url) = thumbnail {
+            embed = embed.thumbnail(url);
+        }
+
+        // Highest threshold the stock reaches decides the color
+        if let Some(color) = self.stock_colors.iter().rev().find(|c| ctx.stock >= c.min_stock) {
+            embed = embed.color(color.color);
+        }
+
+        if let Ok(timestamp) = Timestamp::from_unix_timestamp(ctx.timestamp as i64) {
+            embed = embed.timestamp(timestamp);
+        }
+
+        embed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context() -> AlertContext<'static> {
+        AlertContext {
+            client_id: 2,
+            item_name: "Sword",
+            item_id: 20067,
+            stock: 3,
+            price: 104,
+            timestamp: 1100000000,
+            thumbnail_url: None,
+        }
+    }
+
+    #[test]
+    fn renders_all_placeholders() {
+        let template = MessageTemplate::parse("{{{client}}} {item}#{id} x{stock} @{price} t={timestamp}").unwrap();
+        assert_eq!(template.render(&context()), "{2} Sword#20067 x3 @104 t=1100000000");
+    }
+
+    #[test]
+    fn default_template_matches_legacy_message() {
+        let renderer = AlertRenderer::new(&AlertTemplateConfig::default()).unwrap();
+        let alert = renderer.render(&context());
+        assert_eq!(alert.content, "@everyone Client 2: Item: Sword (ID: 20067) - Stock: 3");
+        assert!(alert.embed.is_none());
+    }
+
+    #[test]
+    fn rejects_bad_templates() {
+        assert!(matches!(MessageTemplate::parse("{nope}"), Err(TemplateError::UnknownPlaceholder(_))));
+        assert!(matches!(MessageTemplate::parse("Item {id"), Err(TemplateError::Unclosed(5))));
+    }
+}
@@ -0,0 +1,283 @@
+use arc_swap::ArcSwap;
+use huffman_decoder::data::catalog::Catalog;
+use huffman_decoder::data::format::{ItemList, ItemRecord};
+use huffman_decoder::data::history::{HistoryConfig, HistoryReader, HistoryStore};
+use huffman_decoder::huffman::encoder::encode;
+use huffman_decoder::memory::transport::FrameSource;
+use huffman_decoder::service::alert_queue::Alert;
+use huffman_decoder::service::dedup::{AlertDeduplicator, DedupConfig};
+use huffman_decoder::service::pipeline::{AlertOutput, FrameReport, Pipeline};
+use huffman_decoder::service::reader_service::parse_stock_records;
+use huffman_decoder::service::rules::{default_rules, Condition, Rule, RuleEngine, Sink};
+use huffman_decoder::service::simulator::{SimulatorConfig, StockSimulator};
+use huffman_decoder::service::template::{AlertRenderer, AlertTemplateConfig};
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind};
+use std::sync::Arc;
+use std::time::Duration;
+
+const T0: u64 = 1_700_000_000_000;
+
+// Frames as a capture would hold them: client id, receive time (Unix millis), raw payload
+#[derive(Default)]
+struct ScriptedSource {
+    frames: VecDeque<(u32, u64, Vec<u8>)>,
+    recorded_at: Option<u64>,
+}
+
+impl ScriptedSource {
+    fn push(&mut self, client_id: u32, received_at: u64, payload: &str) {
+        self.frames.push_back((client_id, received_at, encode(payload.as_bytes()).unwrap()));
+    }
+
+    fn push_raw(&mut self, client_id: u32, received_at: u64, frame: Vec<u8>) {
+        self.frames.push_back((client_id, received_at, frame));
+    }
+}
+
+impl FrameSource for ScriptedSource {
+    async fn recv_with<R>(&mut self, f: impl FnOnce(u32, &[u8]) -> R) -> io::Result<R> {
+        let (client_id, received_at, frame) = self.frames
+            .pop_front()
+            .ok_or_else(|| io::Error::new(ErrorKind::UnexpectedEof, "no more frames"))?;
+        self.recorded_at = Some(received_at);
+        Ok(f(client_id, &frame))
+    }
+
+    fn recorded_at(&self) -> Option<u64> {
+        self.recorded_at
+    }
+}
+
+#[derive(Default)]
+struct Collected(Vec<(u32, Sink, String)>);
+
+impl AlertOutput for Collected {
+    fn deliver(&mut self, client_id: u32, sink: Sink, alert: Alert) {
+        self.0.push((client_id, sink, alert.content));
+    }
+}
+
+fn list(items: &[(u32, &str)]) -> ItemList {
+    items.iter().map(|&(id, name)| (id, ItemRecord::named(name))).collect()
+}
+
+// `all` is list one and `bloody` list two of the default rule
+fn catalog() -> Catalog {
+    let mut catalog = Catalog::new();
+    catalog.insert("all", list(&[(20067, "Sword"), (20069, "Shield"), (21021, "Bow")]));
+    catalog.insert("bloody", list(&[(20069, "Bloody Shield")]));
+    catalog
+}
+
+fn pipeline(catalog: Catalog, rules: Vec<Rule>, cooldown: Duration) -> Pipeline<Collected> {
+    Pipeline::new(
+        Arc::new(ArcSwap::from_pointee(catalog)),
+        RuleEngine::new(rules).unwrap(),
+        AlertRenderer::new(&AlertTemplateConfig::default()).unwrap(),
+        AlertDeduplicator::new(DedupConfig { global_cooldown: cooldown, state_path: None, ..DedupConfig::default() }),
+        Collected::default(),
+    )
+}
+
+fn discord(client_id: u32, item: &str, id: u32, stock: u32) -> (u32, Sink, String) {
+    let content = format!("@everyone Client {}: Item: {} (ID: {}) - Stock: {}", client_id, item, id, stock);
+    (client_id, Sink::Discord, content)
+}
+
+#[tokio::test]
+async fn golden_frame_alerts_for_items_in_list_one_and_not_list_two() {
+    let mut pipeline = pipeline(catalog(), default_rules(), Duration::from_secs(300));
+    let mut source = ScriptedSource::default();
+    source.push(
+        1,
+        T0,
+        "20067-3-104-1700000000|20069-1-47-1700000000|21021-0-447-1700000000|30000-5-1-1700000000|",
+    );
+
+    let report = pipeline.next_frame(&mut source).await.unwrap();
+    // Shield is in both lists, Bow is sold out and 30000 is in neither
+    assert_eq!(report, FrameReport { client_id: 1, records: 4, matched: 1, alerts: 1 });
+    assert_eq!(pipeline.output().0, vec![discord(1, "Sword", 20067, 3)]);
+
+    let end = pipeline.next_frame(&mut source).await.unwrap_err();
+    assert_eq!(end.kind(), ErrorKind::UnexpectedEof);
+}
+
+#[tokio::test]
+async fn leaving_list_two_starts_alerting_on_the_next_frame() {
+    let catalogs = Arc::new(ArcSwap::from_pointee(catalog()));
+    let mut pipeline = Pipeline::new(
+        Arc::clone(&catalogs),
+        RuleEngine::new(default_rules()).unwrap(),
+        AlertRenderer::new(&AlertTemplateConfig::default()).unwrap(),
+        AlertDeduplicator::new(DedupConfig { state_path: None, ..DedupConfig::default() }),
+        Collected::default(),
+    );
+    let mut source = ScriptedSource::default();
+    source.push(2, T0, "20069-4-47-1700000000|");
+    source.push(2, T0 + 1000, "20069-4-47-1700000001|");
+
+    assert_eq!(pipeline.next_frame(&mut source).await.unwrap().alerts, 0);
+
+    // As a reload would: the same lists, with the shield no longer bloody
+    let mut reloaded = catalog();
+    reloaded.insert("bloody", ItemList::default());
+    catalogs.store(Arc::new(reloaded));
+
+    assert_eq!(pipeline.next_frame(&mut source).await.unwrap().alerts, 1);
+    assert_eq!(pipeline.output().0, vec![discord(2, "Shield", 20069, 4)]);
+}
+
+#[tokio::test]
+async fn malformed_frames_are_invalid_data_and_the_next_frame_still_alerts() {
+    let mut pipeline = pipeline(catalog(), default_rules(), Duration::from_secs(300));
+    let mut source = ScriptedSource::default();
+
+    let golden = encode(b"20067-3-104-1700000000|").unwrap();
+    source.push_raw(0, T0, golden[..golden.len() - 3].to_vec());
+    source.push_raw(0, T0, b"garbage".to_vec());
+    source.push_raw(0, T0, Vec::new());
+    // A header claiming a single symbol would never consume a bit
+    let mut one_symbol = golden.clone();
+    one_symbol[8..12].copy_from_slice(&1u32.to_le_bytes());
+    source.push_raw(0, T0, one_symbol);
+    // Three entries naming the same byte would collapse into a single leaf
+    let mut repeated = golden.clone();
+    repeated[8..12].copy_from_slice(&3u32.to_le_bytes());
+    for index in 0..3 {
+        repeated[12 + index * 8 + 4] = b'0';
+    }
+    source.push_raw(0, T0, repeated);
+    // More packed bits than the frame could hold
+    let symbols = u32::from_le_bytes(golden[8..12].try_into().unwrap()) as usize;
+    let mut overrun = golden.clone();
+    overrun[12 + symbols * 8..16 + symbols * 8].copy_from_slice(&u32::MAX.to_le_bytes());
+    source.push_raw(0, T0, overrun);
+    source.push_raw(0, T0, golden);
+
+    for _ in 0..6 {
+        let err = pipeline.next_frame(&mut source).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData, "{}", err);
+    }
+    assert!(pipeline.output().0.is_empty());
+
+    assert_eq!(pipeline.next_frame(&mut source).await.unwrap().alerts, 1);
+    assert_eq!(pipeline.output().0, vec![discord(0, "Sword", 20067, 3)]);
+}
+
+#[tokio::test]
+async fn payloads_that_are_not_stock_records_alert_nothing() {
+    let mut pipeline = pipeline(catalog(), default_rules(), Duration::from_secs(300));
+    let mut source = ScriptedSource::default();
+    source.push(0, T0, "not-a|stock-record|");
+    source.push(0, T0, "20067--|-3-|");
+
+    for _ in 0..2 {
+        let report = pipeline.next_frame(&mut source).await.unwrap();
+        assert!(report.records > 0);
+        assert_eq!(report.matched, 0);
+    }
+    assert!(pipeline.output().0.is_empty());
+}
+
+#[tokio::test]
+async fn unknown_clients_are_alerted_under_their_own_id() {
+    let rules = vec![
+        Rule {
+            name: "known".to_string(),
+            sink: Sink::Discord,
+            when: Condition::All(vec![Condition::InList("all".to_string()), Condition::ClientIn(vec![0, 1, 2, 3])]),
+        },
+        Rule {
+            name: "audit".to_string(),
+            sink: Sink::Log,
+            when: Condition::InList("all".to_string()),
+        },
+    ];
+    let mut pipeline = pipeline(catalog(), rules, Duration::from_secs(300));
+    let mut source = ScriptedSource::default();
+    source.push(3, T0, "20067-3-104-1700000000|");
+    source.push(42, T0, "20067-3-104-1700000000|");
+
+    pipeline.next_frame(&mut source).await.unwrap();
+    let report = pipeline.next_frame(&mut source).await.unwrap();
+    assert_eq!((report.client_id, report.alerts), (42, 1));
+
+    // Only the log rule covers client 42; cooldowns are per client, so it isn't held back by 3's alert
+    let (client_3, unknown) = pipeline.output().0.split_at(2);
+    assert_eq!(client_3.iter().map(|(_, sink, _)| *sink).collect::<Vec<_>>(), vec![Sink::Log, Sink::Discord]);
+    assert_eq!(unknown, [(42, Sink::Log, discord(42, "Sword", 20067, 3).2)]);
+}
+
+#[tokio::test]
+async fn empty_results_alert_nothing_and_record_no_history() {
+    let dir = std::env::temp_dir().join(format!("h278_pipeline_history_{}", std::process::id()));
+    let config = HistoryConfig { enabled: true, dir: dir.to_string_lossy().into_owned(), ..HistoryConfig::default() };
+    let mut pipeline = pipeline(catalog(), default_rules(), Duration::from_secs(300))
+        .with_history(HistoryStore::open(&config).unwrap());
+
+    let mut source = ScriptedSource::default();
+    source.push(1, T0, "");
+    source.push(1, T0 + 1, "|||");
+    source.push(1, T0 + 2, "20067-0-104-1700000000|");
+
+    for records in [0, 0, 1] {
+        let report = pipeline.next_frame(&mut source).await.unwrap();
+        assert_eq!((report.records, report.matched, report.alerts), (records, 0, 0));
+    }
+    assert!(pipeline.output().0.is_empty());
+    drop(pipeline);
+
+    let frames = HistoryReader::new(&dir).frames(0, u64::MAX).unwrap();
+    assert_eq!(frames.len(), 1);
+    assert_eq!((frames[0].client_id, frames[0].received_at), (1, T0 + 2));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn cooldowns_run_on_the_recorded_receive_times() {
+    let mut pipeline = pipeline(catalog(), default_rules(), Duration::from_secs(60));
+    let mut source = ScriptedSource::default();
+    for offset in [0, 59_999, 60_000] {
+        source.push(1, T0 + offset, "20067-3-104-1700000000|");
+    }
+
+    let alerts: Vec<(usize, usize)> = [
+        pipeline.next_frame(&mut source).await.unwrap(),
+        pipeline.next_frame(&mut source).await.unwrap(),
+        pipeline.next_frame(&mut source).await.unwrap(),
+    ].iter().map(|report| (report.matched, report.alerts)).collect();
+    assert_eq!(alerts, vec![(1, 1), (1, 0), (1, 1)]);
+}
+
+#[tokio::test]
+async fn simulated_frames_alert_for_every_listed_item_in_stock() {
+    let config = SimulatorConfig { items: 300, clients: 3, restock_probability: 0.05, ..SimulatorConfig::default() };
+    let mut catalog = Catalog::new();
+    catalog.insert("all", (0..300).map(|offset| (20000 + offset, ItemRecord::named("Item"))).collect());
+    catalog.insert("bloody", list(&[(20001, "Bloody"), (20002, "Bloody")]));
+    let mut pipeline = pipeline(catalog, default_rules(), Duration::ZERO);
+
+    let mut simulator = StockSimulator::new(config);
+    let mut source = ScriptedSource::default();
+    let mut expected = Vec::new();
+    for frame in 0..30 {
+        let (client_id, payload) = simulator.next_frame(1_700_000_000 + frame);
+        let in_stock = parse_stock_records(payload.as_bytes())
+            .iter()
+            .filter(|record| record.stock > 0 && !(20001..=20002).contains(&record.id))
+            .count();
+        expected.push((client_id, in_stock));
+        source.push(client_id, T0 + frame * 100, &payload);
+    }
+
+    for &(client_id, in_stock) in &expected {
+        let report = pipeline.next_frame(&mut source).await.unwrap();
+        assert_eq!((report.client_id, report.records, report.alerts), (client_id, 300, in_stock));
+    }
+    let total: usize = expected.iter().map(|&(_, in_stock)| in_stock).sum();
+    assert!(total > 0);
+    assert_eq!(pipeline.output().0.len(), total);
+}
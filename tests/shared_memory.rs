@@ -0,0 +1,285 @@
+#![cfg(target_os = "linux")]
+
+use huffman_decoder::huffman::decoder::OptimizedHuffmanDecoder;
+use huffman_decoder::huffman::encoder::encode;
+use huffman_decoder::memory::client::SharedMemoryClient;
+use huffman_decoder::memory::mux::SharedMemoryMux;
+use huffman_decoder::memory::ring::{RING_MAGIC, RING_VERSION};
+use huffman_decoder::memory::server::{SegmentOptions, SharedMemoryServer};
+use huffman_decoder::service::reader_service::parse_stock_records;
+use huffman_decoder::service::simulator::{SimulatorConfig, StockSimulator};
+use std::io::ErrorKind;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+
+const FRAMES: u32 = 200;
+
+fn segment_name() -> String {
+    format!("h278_it_{}", std::process::id())
+}
+
+// Everything a server leaves in /dev/shm: segment, lock file and event semaphore
+fn segment_files(name: &str) -> [String; 3] {
+    [
+        format!("/dev/shm/{}", name),
+        format!("/dev/shm/{}.lock", name),
+        format!("/dev/shm/sem.{}_event", name),
+    ]
+}
+
+// Client half of the test below; only does anything when launched by it
+#[test]
+fn client_process() {
+    let Ok(name) = std::env::var("H278_IT_SEGMENT") else {
+        return;
+    };
+
+    let client = SharedMemoryClient::open(&name).unwrap();
+    for n in 0..FRAMES {
+        let payload = format!("frame-{}", n);
+        client.send(n % 4, payload.as_bytes()).unwrap();
+    }
+}
+
+// Waits for a child process to exit, killing it if it takes longer than `timeout`
+fn wait_with_deadline(child: &mut Child, timeout: Duration) -> ExitStatus {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            return status;
+        }
+        if Instant::now() >= deadline {
+            child.kill().unwrap();
+            child.wait().unwrap();
+            panic!("child process still running after {:?}", timeout);
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[tokio::test]
+async fn server_receives_frames_from_client_process() {
+    let name = segment_name();
+    let mut server = SharedMemoryServer::new(&name, 1024 * 1024).unwrap();
+
+    let mut child = Command::new(std::env::current_exe().unwrap())
+        .args(["--exact", "client_process", "--test-threads=1"])
+        .env("H278_IT_SEGMENT", &name)
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let mut received = Vec::new();
+    let receive = tokio::time::timeout(Duration::from_secs(30), async {
+        loop {
+            while let Some(frame) = server.process_data().unwrap() {
+                received.push(frame);
+            }
+            if received.len() >= FRAMES as usize {
+                return;
+            }
+            server.wait_async().await.unwrap();
+        }
+    })
+    .await;
+    if receive.is_err() {
+        let status = child.try_wait().unwrap();
+        let _ = child.kill();
+        panic!("received {} of {} frames before the deadline; client exit status {:?}", received.len(), FRAMES, status);
+    }
+
+    let status = wait_with_deadline(&mut child, Duration::from_secs(10));
+    assert!(status.success(), "client exited with {}", status);
+    for (n, (client_id, payload)) in received.into_iter().enumerate() {
+        assert_eq!(client_id, n as u32 % 4);
+        assert_eq!(payload, format!("frame-{}", n).into_bytes());
+    }
+}
+
+#[test]
+fn client_fails_cleanly_without_a_server() {
+    let err = SharedMemoryClient::open("h278_it_missing_segment").err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+}
+
+#[tokio::test]
+async fn async_recv_leaves_the_runtime_free() {
+    let name = format!("h278_it_async_{}", std::process::id());
+    let mut server = SharedMemoryServer::new(&name, 1024 * 1024).unwrap();
+
+    // The producer is a task on the same single-threaded runtime, so a blocking wait in recv
+    // would deadlock the test
+    let producer = tokio::spawn({
+        let name = name.clone();
+        async move {
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            SharedMemoryClient::open(&name).unwrap().send(1, b"hello").unwrap();
+        }
+    });
+
+    let frame = server.recv().await.unwrap();
+    assert_eq!(frame, (1, b"hello".to_vec()));
+    producer.await.unwrap();
+
+}
+
+#[test]
+fn second_server_is_refused_and_drop_removes_the_segment() {
+    let name = format!("h278_it_owner_{}", std::process::id());
+    let options = SegmentOptions { mode: 0o600, ..SegmentOptions::default() };
+    let server = SharedMemoryServer::with_options(&name, 1024 * 1024, &options).unwrap();
+    let mode = std::fs::metadata(format!("/dev/shm/{}", name)).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+
+    let err = SharedMemoryServer::new(&name, 1024 * 1024).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::AddrInUse);
+    assert!(err.to_string().contains(&std::process::id().to_string()), "{}", err);
+
+    drop(server);
+    for path in segment_files(&name) {
+        assert!(!Path::new(&path).exists(), "{} left behind", path);
+    }
+    let err = SharedMemoryClient::open(&name).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+}
+
+// Server that dies with a frame queued, for the test below
+#[test]
+fn crashed_server_process() {
+    let Ok(name) = std::env::var("H278_IT_CRASH") else {
+        return;
+    };
+
+    let server = SharedMemoryServer::new(&name, 1024 * 1024).unwrap();
+    SharedMemoryClient::open(&name).unwrap().send(1, b"stale").unwrap();
+    std::mem::forget(server);
+    std::process::exit(0);
+}
+
+#[test]
+fn server_resets_a_segment_left_by_a_crash() {
+    let name = format!("h278_it_crash_{}", std::process::id());
+    let status = Command::new(std::env::current_exe().unwrap())
+        .args(["--exact", "crashed_server_process", "--test-threads=1"])
+        .env("H278_IT_CRASH", &name)
+        .stdout(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert!(Path::new(&format!("/dev/shm/{}", name)).exists());
+
+    let exclusive = SegmentOptions { exclusive_create: true, ..SegmentOptions::default() };
+    let err = SharedMemoryServer::with_options(&name, 1024 * 1024, &exclusive).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+
+    let mut server = SharedMemoryServer::new(&name, 1024 * 1024).unwrap();
+    assert!(server.process_data().unwrap().is_none());
+    SharedMemoryClient::open(&name).unwrap().send(2, b"fresh").unwrap();
+    assert!(server.wait_for_data());
+    assert_eq!(server.process_data().unwrap(), Some((2, b"fresh".to_vec())));
+    assert!(server.process_data().unwrap().is_none());
+}
+
+#[test]
+fn server_refuses_to_reset_segments_it_does_not_own() {
+    let name = format!("h278_it_foreign_{}", std::process::id());
+    let path = format!("/dev/shm/{}", name);
+
+    std::fs::write(&path, b"someone else's data").unwrap();
+    let err = SharedMemoryServer::new(&name, 1024 * 1024).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+    assert_eq!(std::fs::read(&path).unwrap(), b"someone else's data");
+
+    // The lock is free, so an owner PID that is running again belongs to someone else
+    let owner = std::os::unix::process::parent_id();
+    let header: Vec<u8> = [RING_MAGIC, RING_VERSION, 4, 4096, owner].iter().flat_map(|field| field.to_ne_bytes()).collect();
+    std::fs::write(&path, &header).unwrap();
+    drop(SharedMemoryServer::new(&name, 1024 * 1024).unwrap());
+
+    let old_version: Vec<u8> = [RING_MAGIC, RING_VERSION - 1].iter().flat_map(|field| field.to_ne_bytes()).collect();
+    std::fs::write(&path, &old_version).unwrap();
+    let err = SharedMemoryServer::new(&name, 1024 * 1024).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+    for path in segment_files(&name) {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+#[test]
+fn a_flooded_segment_leaves_the_others_writable() {
+    let names = ["noisy", "quiet"].map(|role| format!("h278_it_mux_{}_{}", role, std::process::id()));
+    let mut mux = SharedMemoryMux::new();
+    for name in &names {
+        mux.add(name, 1024 * 1024, &SegmentOptions::default()).unwrap();
+    }
+
+    let noisy = SharedMemoryClient::open(&names[0]).unwrap();
+    let quiet = SharedMemoryClient::open(&names[1]).unwrap();
+    let mut flooded = 0;
+    while noisy.try_send(1, b"noise").is_ok() {
+        flooded += 1;
+    }
+    quiet.try_send(2, b"signal").unwrap();
+    quiet.try_send(2, b"signal").unwrap();
+
+    // Round robin: the quiet segment's frames come out second and fourth, not after the flood
+    let mut order = Vec::new();
+    while let Some((index, frame)) = mux.next_frame().unwrap() {
+        order.push((index, frame.client_id(), frame.to_vec()));
+    }
+    assert_eq!(order.len(), flooded + 2);
+    assert_eq!(order[1], (1, 2, b"signal".to_vec()));
+    assert_eq!(order[3], (1, 2, b"signal".to_vec()));
+    assert!(order[4..].iter().all(|(index, _, _)| *index == 0));
+}
+
+#[tokio::test]
+async fn mux_wakes_for_whichever_segment_is_signalled() {
+    let names = ["a", "b", "c"].map(|role| format!("h278_it_mux_wait_{}_{}", role, std::process::id()));
+    let mut mux = SharedMemoryMux::new();
+    for name in &names {
+        mux.add(name, 1024 * 1024, &SegmentOptions::default()).unwrap();
+    }
+    let err = mux.add(&names[0], 1024 * 1024, &SegmentOptions::default()).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+
+    for (index, name) in names.iter().enumerate().rev() {
+        let producer = tokio::spawn({
+            let name = name.clone();
+            async move {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+                SharedMemoryClient::open(&name).unwrap().send(index as u32, name.as_bytes()).unwrap();
+            }
+        });
+
+        assert_eq!(mux.wait_async().await.unwrap(), index);
+        let (from, frame) = mux.next_frame().unwrap().unwrap();
+        assert_eq!((from, frame.client_id(), &*frame), (index, index as u32, name.as_bytes()));
+        drop(frame);
+        producer.await.unwrap();
+    }
+}
+
+#[test]
+fn simulated_frames_decode_to_the_generated_records() {
+    let name = format!("h278_it_sim_{}", std::process::id());
+    let mut server = SharedMemoryServer::new(&name, 1024 * 1024).unwrap();
+    let client = SharedMemoryClient::open(&name).unwrap();
+    let config = SimulatorConfig { items: 200, restock_probability: 0.05, ..SimulatorConfig::default() };
+    let mut simulator = StockSimulator::new(config);
+    let mut decoder = OptimizedHuffmanDecoder::new();
+
+    for _ in 0..20 {
+        let (client_id, payload) = simulator.next_frame(1_700_000_000);
+        client.send(client_id, &encode(payload.as_bytes()).unwrap()).unwrap();
+
+        let (received_client, frame) = server.process_data().unwrap().unwrap();
+        assert_eq!(received_client, client_id);
+        let records = parse_stock_records(&decoder.decode_to_bytes(&frame));
+        assert_eq!(records, parse_stock_records(payload.as_bytes()));
+    }
+    assert!(simulator.in_stock() > 0);
+}